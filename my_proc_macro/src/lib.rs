@@ -11,7 +11,7 @@ pub fn reg8(item: TokenStream) -> TokenStream {
     TokenStream::from_iter(item.into_iter().map(|t| {
         format!(
             "pub const fn {}(&self) -> &Reg<u8> {{ self.r8(RegId8::{}) }}",
-            t.to_string(),
+            t,
             t.to_string().to_uppercase()
         )
         .parse::<TokenStream>()
@@ -24,7 +24,7 @@ pub fn reg16(item: TokenStream) -> TokenStream {
     TokenStream::from_iter(item.into_iter().map(|t| {
         format!(
             "pub const fn {}(&self) -> &Reg<u16> {{ self.r16(RegId16::{}) }}",
-            t.to_string(),
+            t,
             t.to_string().to_uppercase()
         )
         .parse::<TokenStream>()
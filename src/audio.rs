@@ -1,3 +1,5 @@
+use crate::state::{Reader, State, StateError, Writer};
+
 pub struct Apu {
     nr10: u8, //Ch 1 sweep register
     nr11: u8,
@@ -68,7 +70,7 @@ impl Apu {
 
             0xFF30..=0xFF3F => self.wave_pattern_ram[(addr - 0xFF30) as usize] = val,
 
-            0xFF10..0xFF40 => { },
+            0xFF15 | 0xFF1F | 0xFF27..0xFF30 => { },
             _ => unreachable!("invalid APU register address {:04X}", addr)
         }
     }
@@ -103,7 +105,7 @@ impl Apu {
 
             0xFF30..=0xFF3F => self.wave_pattern_ram[(addr - 0xFF30) as usize],
 
-            0xFF10..0xFF40 => 0xFF,
+            0xFF15 | 0xFF1F | 0xFF27..0xFF30 => 0xFF,
             _ => unreachable!("invalid APU register address {:04X}", addr)
         }
     }
@@ -113,3 +115,26 @@ impl Apu {
         (0.0, 0.0)
     }
 }
+
+impl State for Apu {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&[self.nr10, self.nr11, self.nr12, self.nr13, self.nr14]);
+        w.bytes(&[self.nr21, self.nr22, self.nr23, self.nr24]);
+        w.bytes(&[self.nr30, self.nr31, self.nr32, self.nr33, self.nr34]);
+        w.bytes(&[self.nr41, self.nr42, self.nr43, self.nr44]);
+        w.bytes(&[self.nr50, self.nr51, self.nr52]);
+        w.bytes(&self.wave_pattern_ram);
+        w.u64(self.cycle_count);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        [self.nr10, self.nr11, self.nr12, self.nr13, self.nr14] = r.take(5)?.try_into().unwrap();
+        [self.nr21, self.nr22, self.nr23, self.nr24] = r.take(4)?.try_into().unwrap();
+        [self.nr30, self.nr31, self.nr32, self.nr33, self.nr34] = r.take(5)?.try_into().unwrap();
+        [self.nr41, self.nr42, self.nr43, self.nr44] = r.take(4)?.try_into().unwrap();
+        [self.nr50, self.nr51, self.nr52] = r.take(3)?.try_into().unwrap();
+        r.bytes(&mut self.wave_pattern_ram)?;
+        self.cycle_count = r.u64()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,269 @@
+use crate::boot::Model;
+use crate::bus::Bus;
+use crate::cpu::{Cpu, Registers};
+use crate::rtc::RTC_FOOTER_LEN;
+use crate::state::{Reader, StateError, Writer};
+
+// Best Effort Save State, the format SameBoy and others append to their
+// own states: the memory buffers anywhere in the file, then a chain of
+// blocks found through an 8 byte footer
+// see https://github.com/LIJI32/SameBoy/blob/master/BESS.md
+//
+// only what a DMG has and what holds between instructions is carried over;
+// the PPU starts the stored line over and the APU its channels
+const MAGIC: [u8; 4] = *b"BESS";
+const NAME: &[u8] = b"gb.rs";
+
+const CORE_MAJOR: u16 = 1;
+const CORE_MINOR: u16 = 1;
+const CORE_LEN: usize = 0xD0;
+const INFO_LEN: usize = 0x12;
+
+const EXEC_RUNNING: u8 = 0;
+const EXEC_HALTED: u8 = 1;
+const EXEC_STOPPED: u8 = 2;
+
+// family, model and revision, a space where there is nothing to tell
+fn model_code(model: Model) -> &'static [u8; 4] {
+    match model {
+        Model::Dmg0 => b"GD0 ",
+        Model::Dmg => b"GD  ",
+        Model::Mgb => b"GM  ",
+        Model::Sgb => b"SN  ",
+        Model::Cgb => b"CCE ",
+    }
+}
+
+fn decode_model(code: &[u8]) -> Result<Model, StateError> {
+    match code {
+        [b'G', b'D', b'0', _] => Ok(Model::Dmg0),
+        [b'G', b'M', ..] => Ok(Model::Mgb),
+        [b'G', ..] => Ok(Model::Dmg),
+        [b'S', ..] => Ok(Model::Sgb),
+        [b'C', ..] => Ok(Model::Cgb),
+        _ => Err(StateError::Invalid),
+    }
+}
+
+fn block(w: &mut Writer, id: &[u8; 4], len: usize) {
+    w.bytes(id);
+    w.u32(len as u32);
+}
+
+// writes out a memory buffer, returning the size and offset CORE points at
+fn buffer(w: &mut Writer, data: &[u8]) -> (u32, u32) {
+    let offset = w.len() as u32;
+    w.bytes(data);
+    (data.len() as u32, offset)
+}
+
+// the I/O range as reads see it, but for the register that can't be read
+fn read_io(bus: &Bus, addr: u16) -> u8 {
+    match addr {
+        // bit 0 set once the boot ROM is unmapped
+        0xFF50 => if bus.boot_map { 0xFE } else { 0xFF },
+        _ => bus.read(addr),
+    }
+}
+
+pub(crate) fn save(w: &mut Writer, cpu: &Cpu, bus: &Bus, model: Model, now: u64) {
+    let wram = buffer(w, &bus.wram);
+    let vram = buffer(w, &bus.ppu.vram);
+    let cart_ram = buffer(w, bus.cart.ram());
+    let oam = buffer(w, bus.ppu.oam());
+    let hram = buffer(w, &bus.hram);
+    // DMG has no color palettes
+    let none = (0, 0);
+
+    let first_block = w.len();
+
+    block(w, b"NAME", NAME.len());
+    w.bytes(NAME);
+
+    let rom = &bus.cart.rom_image;
+    block(w, b"INFO", INFO_LEN);
+    w.bytes(&rom[0x134..0x144]);
+    w.bytes(&rom[0x14E..0x150]);
+
+    block(w, b"CORE", CORE_LEN);
+    w.u16(CORE_MAJOR);
+    w.u16(CORE_MINOR);
+    w.bytes(model_code(model));
+    let Registers { af, bc, de, hl, sp, pc } = cpu.registers();
+    for reg in [pc, af, bc, de, hl, sp] {
+        w.u16(reg);
+    }
+    w.bool(cpu.ime());
+    w.u8(bus.intr.read_ie());
+    w.u8(if cpu.stopped() {
+        EXEC_STOPPED
+    } else if cpu.halted() {
+        EXEC_HALTED
+    } else {
+        EXEC_RUNNING
+    });
+    w.u8(0);
+    for addr in 0xFF00..0xFF80 {
+        w.u8(read_io(bus, addr));
+    }
+    for (size, offset) in [wram, vram, cart_ram, oam, hram, none, none] {
+        w.u32(size);
+        w.u32(offset);
+    }
+
+    let writes = bus.cart.mbc_writes();
+    block(w, b"MBC ", writes.clone().count() * 3);
+    for (addr, val) in writes {
+        w.u16(addr);
+        w.u8(val);
+    }
+
+    if let Some(rtc) = bus.cart.rtc() {
+        block(w, b"RTC ", RTC_FOOTER_LEN);
+        w.bytes(&rtc.footer(now));
+    }
+
+    block(w, b"END ", 0);
+
+    w.u32(first_block as u32);
+    w.bytes(&MAGIC);
+}
+
+// a BESS file checked against the inserted ROM, every block and buffer it
+// points to known to be in bounds
+pub(crate) struct Bess<'a> {
+    data: &'a [u8],
+    core: &'a [u8],
+    mbc: Option<&'a [u8]>,
+    rtc: Option<&'a [u8]>,
+}
+
+impl<'a> Bess<'a> {
+    pub fn parse(data: &'a [u8], rom: &[u8]) -> Result<Bess<'a>, StateError> {
+        let footer = data.len().checked_sub(8).ok_or(StateError::BadMagic)?;
+        if data[footer + 4..] != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let start = u32::from_le_bytes(data[footer..footer + 4].try_into().unwrap()) as usize;
+        let mut r = Reader::new(data.get(start..footer).ok_or(StateError::Invalid)?);
+
+        let (mut core, mut info, mut mbc, mut rtc) = (None, None, None, None);
+        loop {
+            let id = r.take(4)?;
+            let len = r.u32()? as usize;
+            let body = r.take(len)?;
+            match id {
+                b"CORE" => core = Some(body),
+                b"INFO" => info = Some(body),
+                b"MBC " => mbc = Some(body),
+                b"RTC " => rtc = Some(body),
+                b"END " => break,
+                // NAME, and the CGB and SGB blocks
+                _ => {}
+            }
+        }
+
+        let core = core.ok_or(StateError::Invalid)?;
+        let mut r = Reader::new(core);
+        let major = r.u16()?;
+        if major != CORE_MAJOR {
+            return Err(StateError::Version(major.into()));
+        }
+        // later minor versions may only grow the block
+        if core.len() < CORE_LEN {
+            return Err(StateError::Invalid);
+        }
+        decode_model(&core[0x04..0x08])?;
+        if core[0x16] > EXEC_STOPPED || core[0x18 + 0x44] > 153 {
+            return Err(StateError::Invalid);
+        }
+
+        if let Some(info) = info {
+            if info.len() != INFO_LEN {
+                return Err(StateError::Invalid);
+            }
+            if info[..0x10] != rom[0x134..0x144] || info[0x10..] != rom[0x14E..0x150] {
+                return Err(StateError::WrongRom);
+            }
+        }
+        if mbc.is_some_and(|mbc| !mbc.len().is_multiple_of(3)) || rtc.is_some_and(|rtc| rtc.len() != RTC_FOOTER_LEN) {
+            return Err(StateError::Invalid);
+        }
+
+        let bess = Bess { data, core, mbc, rtc };
+        for i in 0..7 {
+            bess.buffer(i)?;
+        }
+        Ok(bess)
+    }
+
+    pub fn model(&self) -> Model {
+        decode_model(&self.core[0x04..0x08]).unwrap()
+    }
+
+    fn core_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes(self.core[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn core_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.core[offset..offset + 4].try_into().unwrap())
+    }
+
+    // WRAM, VRAM, cart RAM, OAM, HRAM and the two color palettes in turn
+    fn buffer(&self, i: usize) -> Result<&'a [u8], StateError> {
+        let size = self.core_u32(0x98 + i * 8) as usize;
+        let offset = self.core_u32(0x9C + i * 8) as usize;
+        let end = offset.checked_add(size).ok_or(StateError::Invalid)?;
+        self.data.get(offset..end).ok_or(StateError::Invalid)
+    }
+
+    // CGB states have more WRAM and VRAM, whose first banks are what a DMG
+    // sees; RAM of a differently sized cart is matched up from the start
+    fn copy(&self, i: usize, dst: &mut [u8]) {
+        let src = self.buffer(i).unwrap();
+        let len = src.len().min(dst.len());
+        dst[..len].copy_from_slice(&src[..len]);
+    }
+
+    // onto a machine that was just reset with the model of the state
+    pub fn apply(&self, cpu: &Cpu, bus: &mut Bus, bootrom: bool, now: u64) {
+        let [pc, af, bc, de, hl, sp] = [0x08, 0x0A, 0x0C, 0x0E, 0x10, 0x12].map(|i| self.core_u16(i));
+        cpu.set_registers(Registers { af, bc, de, hl, sp, pc });
+        cpu.set_ime(self.core[0x14] != 0);
+        bus.intr.write_ie(self.core[0x15]);
+        cpu.set_halted(self.core[0x16] == EXEC_HALTED);
+        cpu.set_stopped(self.core[0x16] == EXEC_STOPPED);
+
+        self.copy(0, &mut bus.wram);
+        self.copy(1, &mut bus.ppu.vram);
+        self.copy(2, bus.cart.ram_mut());
+        self.copy(3, bus.ppu.oam_mut());
+        self.copy(4, &mut bus.hram);
+
+        for (i, &val) in self.core[0x18..0x98].iter().enumerate() {
+            let addr = 0xFF00 + i as u16;
+            match addr {
+                0xFF04 => bus.timer.set_counter(u16::from(val) << 8),
+                // as if it had been on all along
+                0xFF40 => bus.ppu.lcdc = val,
+                0xFF44 => bus.ppu.ly = val,
+                // would start a transfer
+                0xFF46 => bus.dma.reg = val,
+                0xFF50 => bus.boot_map = bootrom && val & 0x01 == 0,
+                _ => bus.write(addr, val),
+            }
+        }
+
+        for write in self.mbc.unwrap_or_default().chunks_exact(3) {
+            let addr = u16::from_le_bytes([write[0], write[1]]);
+            // anything outside the mapper registers has no business here
+            if addr < 0x8000 {
+                bus.cart.write_rom(addr, write[2]);
+            }
+        }
+
+        if let (Some(rtc), Some(footer)) = (bus.cart.rtc_mut(), self.rtc) {
+            rtc.set_footer(footer.try_into().unwrap(), now);
+        }
+    }
+}
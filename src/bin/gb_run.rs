@@ -0,0 +1,137 @@
+// headless runner: boot a ROM, run it for a while, dump the screen
+//
+// usage: gb_run [-m MODEL] [-b BOOTROM] [-s STATE] [-f FRAMES | -c CYCLES] [-o OUT.ppm] [--scanline] ROM
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::process::ExitCode;
+use std::time::SystemTime;
+
+use gb_rs::{CYCLES_PER_FRAME, FRAME_HEIGHT, FRAME_WIDTH, GB, Model, Renderer};
+
+const USAGE: &str = "usage: gb_run [-m MODEL] [-b BOOTROM] [-s STATE] [-f FRAMES | -c CYCLES] [-o OUT.ppm] [--scanline] ROM\n\
+                     MODEL is one of dmg0, dmg (default), mgb, sgb, cgb\n\
+                     STATE is a BESS save state, e.g. from SameBoy, which also sets the model\n\
+                     --scanline draws whole lines at once, faster but blind to mid-line effects";
+
+struct Args {
+    rom: String,
+    bootrom: Option<String>,
+    state: Option<String>,
+    model: Model,
+    cycles: usize,
+    output: String,
+    renderer: Renderer,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut rom = None;
+    let mut bootrom = None;
+    let mut state = None;
+    let mut model = Model::Dmg;
+    let mut cycles = 60 * CYCLES_PER_FRAME;
+    let mut output = String::from("frame.ppm");
+    let mut renderer = Renderer::Fifo;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{name} expects a value"));
+        match arg.as_str() {
+            "-b" | "--bootrom" => bootrom = Some(value(&arg)?),
+            "-s" | "--state" => state = Some(value(&arg)?),
+            "-o" | "--output" => output = value(&arg)?,
+            "-m" | "--model" => {
+                model = match value(&arg)?.as_str() {
+                    "dmg0" => Model::Dmg0,
+                    "dmg" => Model::Dmg,
+                    "mgb" => Model::Mgb,
+                    "sgb" => Model::Sgb,
+                    "cgb" => Model::Cgb,
+                    m => return Err(format!("unknown model {m}\n{USAGE}")),
+                };
+            }
+            "-f" | "--frames" => {
+                let frames: usize = value(&arg)?.parse().map_err(|e| format!("{arg}: {e}"))?;
+                cycles = frames * CYCLES_PER_FRAME;
+            }
+            "-c" | "--cycles" => {
+                cycles = value(&arg)?.parse().map_err(|e| format!("{arg}: {e}"))?;
+            }
+            "--scanline" => renderer = Renderer::Scanline,
+            "-h" | "--help" => return Err(String::from(USAGE)),
+            _ if arg.starts_with('-') => return Err(format!("unknown option {arg}\n{USAGE}")),
+            _ => rom = Some(arg),
+        }
+    }
+
+    Ok(Args {
+        rom: rom.ok_or(String::from(USAGE))?,
+        bootrom,
+        state,
+        model,
+        cycles,
+        output,
+        renderer,
+    })
+}
+
+// binary PPM (P6), the alpha channel of the RGBA frame buffer is dropped
+fn write_ppm(path: &str, rgba: &[u8]) -> io::Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write!(w, "P6\n{FRAME_WIDTH} {FRAME_HEIGHT}\n255\n")?;
+    for px in rgba.chunks_exact(4) {
+        w.write_all(&px[..3])?;
+    }
+    w.flush()
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let mut gb = GB::new();
+    gb.set_model(args.model);
+    gb.set_renderer(args.renderer);
+
+    let rom = fs::read(&args.rom)?;
+    gb.load_rom(&rom).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    let header = gb.header();
+    eprintln!("{} (type {:02X}, version {})", header.title().collect::<String>(), header.cart_type, header.version);
+    if !header.global_checksum_ok(&rom) {
+        eprintln!("warning: global checksum mismatch");
+    }
+
+    if let Some(bootrom) = &args.bootrom {
+        gb.load_bootrom(&fs::read(bootrom)?);
+    }
+
+    if let Some(state) = &args.state {
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_or(0, |t| t.as_secs());
+        gb.load_bess(&fs::read(state)?, now).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    }
+
+    for _ in 0..args.cycles {
+        if gb.tick().is_break() {
+            break;
+        }
+    }
+
+    write_ppm(&args.output, gb.frame_buffer())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("{msg}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("gb_run: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::cpu::Registers;
+
+// the console being emulated, which only matters for the state its boot
+// ROM leaves behind; CGB runs carts in DMG compatibility mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    Dmg0,
+    Dmg,
+    Mgb,
+    Sgb,
+    Cgb,
+}
+
+// what differs between models once the boot ROM hands over at 0100
+// see https://gbdev.io/pandocs/Power_Up_Sequence.html
+pub(crate) struct PostBoot {
+    pub regs: Registers,
+    pub div: u16, // full system counter, DIV is the upper byte
+    pub sc: u8,
+    pub nr52: u8,
+    pub logo: bool, // whether the Nintendo logo is left in VRAM
+}
+
+impl Model {
+    // header_checksum decides H and C on the models that don't hardcode F
+    pub(crate) fn post_boot(self, header_checksum: u8) -> PostBoot {
+        let hc = if header_checksum == 0 { 0x00 } else { 0x30 };
+        let (af, bc, de, hl) = match self {
+            Model::Dmg0 => (0x0100, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => (0x0180 | hc, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF80 | hc, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            // B, H and L really depend on a hash of the title
+            Model::Cgb => (0x1180, 0x0000, 0x0008, 0x007C),
+        };
+
+        // DMG and MGB match the mooneye boot_div tests, the rest only
+        // get the upper byte right
+        let div = match self {
+            Model::Dmg0 => 0x1830,
+            Model::Dmg | Model::Mgb => 0xABCC,
+            Model::Sgb => 0xD800,
+            Model::Cgb => 0x2678,
+        };
+
+        PostBoot {
+            regs: Registers { af, bc, de, hl, sp: 0xFFFE, pc: 0x0100 },
+            div,
+            sc: if self == Model::Cgb { 0x7F } else { 0x7E },
+            nr52: if self == Model::Sgb { 0xF0 } else { 0xF1 },
+            logo: self != Model::Cgb,
+        }
+    }
+}
+
+// the registered mark the DMG boot ROM draws next to the logo
+const REGISTERED: [u8; 8] = [0x3C, 0x42, 0xB9, 0xA5, 0xB9, 0xA5, 0x42, 0x3C];
+
+// unpacks the header logo into tiles 01..18 as the boot ROM does, every bit
+// doubled horizontally and every row vertically, then maps it at 9904
+pub(crate) fn draw_logo(vram: &mut [u8; 0x2000], logo: &[u8]) {
+    fn double(nibble: u8) -> u8 {
+        (0..4).fold(0, |acc, i| acc | ((nibble >> i & 1) * (0x03 << (2 * i))))
+    }
+
+    // each logo byte fills four rows, only the low bitplane is set
+    let mut rows = logo.iter().flat_map(|&b| [double(b >> 4), double(b >> 4), double(b & 0x0F), double(b & 0x0F)]);
+    for row in vram[0x0010..0x0190].chunks_exact_mut(2) {
+        row[0] = rows.next().unwrap_or(0);
+        row[1] = 0;
+    }
+    for (i, &b) in REGISTERED.iter().enumerate() {
+        vram[0x0190 + 2 * i] = b;
+        vram[0x0190 + 2 * i + 1] = 0;
+    }
+
+    for i in 0..12 {
+        vram[0x1904 + i] = i as u8 + 0x01;
+        vram[0x1924 + i] = i as u8 + 0x0D;
+    }
+    vram[0x1910] = 0x19;
+}
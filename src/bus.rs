@@ -1,9 +1,14 @@
 use crate::audio::Apu;
 use crate::cart::Cart;
+use crate::dma::Dma;
 use crate::graphic::Ppu;
 use crate::intr::{Intr, IntrSrc};
+use crate::serial::Serial;
+use crate::state::{Reader, State, StateError, Writer};
 use crate::timer::Timer;
 
+use core::ptr;
+
 pub(crate) type Addr = u16;
 
 pub(crate) struct Bus {
@@ -11,14 +16,16 @@ pub(crate) struct Bus {
     pub(crate) apu: Apu,
     pub(crate) ppu: Ppu,
     pub(crate) cart: Cart,
+    pub(crate) dma: Dma,
     pub(crate) intr: Intr,
+    pub(crate) serial: Serial,
     pub(crate) timer: Timer,
 
-    joy_state: u8,
+    pub(crate) joy_state: u8,
     pub(crate) joy_sel: u8,
 
-    wram: [u8; 0x2000],
-    hram: [u8; 0x7F],
+    pub(crate) wram: [u8; 0x2000],
+    pub(crate) hram: [u8; 0x7F],
 
     pub(crate) boot_map: bool,
 }
@@ -28,7 +35,9 @@ impl Bus {
         self.apu.init();
         self.ppu.init();
         self.cart.init();
+        self.dma.init();
         self.intr.init();
+        self.serial.init();
         self.timer.init();
 
         self.boot_map = true;
@@ -38,6 +47,34 @@ impl Bus {
         self.joy_state = 0xFF;
     }
 
+    // power cycle everything but the boot ROM, the inserted cart, which
+    // only loses its mapper state, and the choice of PPU renderer; all
+    // parts are valid zeroed as in GB::new
+    pub fn reset(&mut self) {
+        let renderer = self.ppu.renderer;
+        unsafe {
+            ptr::write_bytes(&raw mut self.apu, 0, 1);
+            ptr::write_bytes(&raw mut self.ppu, 0, 1);
+            ptr::write_bytes(&raw mut self.dma, 0, 1);
+            ptr::write_bytes(&raw mut self.intr, 0, 1);
+            ptr::write_bytes(&raw mut self.serial, 0, 1);
+            ptr::write_bytes(&raw mut self.timer, 0, 1);
+        }
+        self.apu.init();
+        self.ppu.init();
+        self.ppu.renderer = renderer;
+        self.dma.init();
+        self.intr.init();
+        self.serial.init();
+        self.timer.init();
+        self.cart.reset();
+
+        self.wram.fill(0);
+        self.hram.fill(0);
+        self.joy_sel = 0;
+        self.joy_state = 0xFF;
+    }
+
     pub fn write_joy_sel(&mut self, value: u8) {
         let previous_matrix = self.read_joystate();
         self.joy_sel = value & 0x30;
@@ -62,7 +99,24 @@ impl Bus {
         result
     }
 
+    // one M-cycle of OAM DMA
+    pub fn tick_dma(&mut self) {
+        if let Some((src, pos)) = self.dma.tick() {
+            let val = self.peek(src);
+            self.ppu.oam_mut()[pos as usize] = val;
+        }
+    }
+
+    // as the CPU sees it, which is nothing but 0xFF below FF00 during DMA
     pub fn read(&self, addr: Addr) -> u8 {
+        if self.dma.blocks(addr) {
+            return 0xFF;
+        }
+        self.peek(addr)
+    }
+
+    // what is at addr, regardless of who has the bus
+    pub fn peek(&self, addr: Addr) -> u8 {
         match addr {
             0x0000..0x0100 => {
                 if self.boot_map {
@@ -79,29 +133,30 @@ impl Bus {
             0xFE00..0xFEA0 => self.ppu.read_oam(addr),
             0xFEA0..0xFF00 => 0xFF, /* Not Used */
             0xFF00 => self.read_joystate(),
-            0xFF01..0xFF03 => 0xFF, /* TODO: serial */
+            0xFF01 => self.serial.sb,
+            0xFF02 => self.serial.read_sc(),
             0xFF03 => 0xFF, /* Unused */
-            0xFF04 => self.timer.div,
+            0xFF04 => self.timer.read_div(),
             0xFF05 => self.timer.tima,
             0xFF06 => self.timer.tma,
-            0xFF07 => self.timer.tac,
+            0xFF07 => self.timer.read_tac(),
             0xFF08..0xFF0F => 0xFF, /* Unused */
             0xFF0F => self.intr.read_if(),
             0xFF10..0xFF40 => self.apu.read(addr),
             0xFF40 => self.ppu.lcdc,
-            0xFF41 => self.ppu.stat,
+            0xFF41 => self.ppu.read_stat(),
             0xFF42 => self.ppu.scy,
             0xFF43 => self.ppu.scx,
             0xFF44 => self.ppu.ly,
             0xFF45 => self.ppu.lyc,
-            0xFF46 => todo!("oam dma"),
+            0xFF46 => self.dma.reg,
             0xFF47 => self.ppu.bgp,
             0xFF48 => self.ppu.obp0,
             0xFF49 => self.ppu.obp1,
-            0xFF4A => self.ppu.wx,
-            0xFF4B => self.ppu.wy,
+            0xFF4A => self.ppu.wy,
+            0xFF4B => self.ppu.wx,
             0xFF4C..0xFF50 => 0xFF, /* DMG Not Used */
-            0xFF50 => todo!("bootrom unmap register read behavior?"),
+            0xFF50 => 0xFF, /* write only */
             0xFF51..0xFF80 => 0xFF, /* DMG Not Used */
             0xFF80..0xFFFF => self.hram[(addr as usize) - 0xFF80],
             0xFFFF => self.intr.read_ie(),
@@ -109,6 +164,9 @@ impl Bus {
     }
 
     pub fn write(&mut self, addr: Addr, val: u8) {
+        if self.dma.blocks(addr) {
+            return;
+        }
         match addr {
             0x0000..0x8000 => self.cart.write_rom(addr, val),
             0x8000..0xA000 => self.ppu.write_vram(addr, val),
@@ -118,37 +176,67 @@ impl Bus {
             0xFE00..0xFEA0 => self.ppu.write_oam(addr, val),
             0xFEA0..0xFF00 => { }, /* Not Used */
             0xFF00 => self.joy_sel = val,
-            0xFF01..0xFF03 => { }, /* TODO: serial */
+            0xFF01 => self.serial.sb = val,
+            0xFF02 => self.serial.write_sc(val),
             0xFF03 => { }, /* Unused */
-            0xFF04 => self.timer.div = 0,
-            0xFF05 => self.timer.tima = val,
+            0xFF04 => self.timer.write_div(),
+            0xFF05 => self.timer.write_tima(val),
             0xFF06 => self.timer.tma = val,
-            0xFF07 => self.timer.tac = val & 0x03,
+            0xFF07 => self.timer.write_tac(val),
             0xFF08..0xFF0F => { }, /* Unused */
             0xFF0F => self.intr.write_if(val),
             0xFF10..0xFF40 => self.apu.write(addr, val),
-            0xFF40 => self.ppu.lcdc = val,
-            0xFF41 => self.ppu.stat = val,
+            0xFF40 => self.ppu.write_lcdc(val),
+            0xFF41 => self.ppu.write_stat(val),
             0xFF42 => self.ppu.scy = val,
             0xFF43 => self.ppu.scx = val,
             0xFF44 => self.ppu.ly = val,
             0xFF45 => self.ppu.lyc = val,
-            0xFF46 => {
-                for i in 0..0xA0 {
-                    // TODO(yhr0x43): proper dma
-                    self.ppu.write_oam(0xFE00 | i, self.read(((val as u16) << 8) | i))
-                }
-            },
+            0xFF46 => self.dma.write(val),
             0xFF47 => self.ppu.bgp = val,
             0xFF48 => self.ppu.obp0 = val,
             0xFF49 => self.ppu.obp1 = val,
-            0xFF4A => self.ppu.wx = val,
-            0xFF4B => self.ppu.wy = val,
+            0xFF4A => self.ppu.wy = val,
+            0xFF4B => self.ppu.wx = val,
             0xFF4C..0xFF50 => { }, /* DMG Not Used */
-            0xFF50 => self.boot_map = false,
+            // there is no way back once the boot ROM is unmapped
+            0xFF50 => self.boot_map &= val == 0,
             0xFF51..0xFF80 => { }, /* DMG Not Used */
             0xFF80..0xFFFF => self.hram[(addr as usize) - 0xFF80] = val,
             0xFFFF => self.intr.write_ie(val),
         };
     }
 }
+
+impl State for Bus {
+    fn save(&self, w: &mut Writer) {
+        self.apu.save(w);
+        self.ppu.save(w);
+        self.cart.save(w);
+        self.dma.save(w);
+        self.intr.save(w);
+        self.serial.save(w);
+        self.timer.save(w);
+
+        w.bytes(&self.wram);
+        w.bytes(&self.hram);
+        w.u8(self.joy_sel);
+        w.bool(self.boot_map);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.apu.load(r)?;
+        self.ppu.load(r)?;
+        self.cart.load(r)?;
+        self.dma.load(r)?;
+        self.intr.load(r)?;
+        self.serial.load(r)?;
+        self.timer.load(r)?;
+
+        r.bytes(&mut self.wram)?;
+        r.bytes(&mut self.hram)?;
+        self.joy_sel = r.u8()? & 0x30;
+        self.boot_map = r.bool()?;
+        Ok(())
+    }
+}
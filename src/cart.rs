@@ -1,8 +1,15 @@
 use crate::bus;
 use crate::gb;
+use crate::rtc::Rtc;
+use crate::state::{Reader, State, StateError, Writer};
 
 use core::cmp;
+use core::fmt;
 
+use alloc::boxed::Box;
+use alloc::vec;
+
+#[derive(Clone, Copy)]
 enum MbcType {
     RomOnly,
     Mbc1,
@@ -34,77 +41,554 @@ enum MbcType {
     HuC1RamBattery,
 }
 
+impl MbcType {
+    fn decode(code: u8) -> Result<MbcType, LoadError> {
+        Ok(match code {
+            0x00 => MbcType::RomOnly,
+            0x01 => MbcType::Mbc1,
+            0x02 => MbcType::Mbc1Ram,
+            0x03 => MbcType::Mbc1RamBattery,
+            0x05 => MbcType::Mbc2,
+            0x06 => MbcType::Mbc2Battery,
+            0x08 => MbcType::RomRam,
+            0x09 => MbcType::RomRamBattery,
+            0x0B => MbcType::Mmm01,
+            0x0C => MbcType::Mmm01Ram,
+            0x0D => MbcType::Mmm01RamBattery,
+            0x0F => MbcType::Mbc3TimerBattery,
+            0x10 => MbcType::Mbc3TimerRamBattery,
+            0x11 => MbcType::Mbc3,
+            0x12 => MbcType::Mbc3Ram,
+            0x13 => MbcType::Mbc3RamBattery,
+            0x19 => MbcType::Mbc5,
+            0x1A => MbcType::Mbc5Ram,
+            0x1B => MbcType::Mbc5RamBattery,
+            0x1C => MbcType::Mbc5Rumble,
+            0x1D => MbcType::Mbc5RumbleRam,
+            0x1E => MbcType::Mbc5RumbleRamBattery,
+            0x20 => MbcType::Mbc6,
+            0x22 => MbcType::Mbc7SensorRumbleRamBattery,
+            0xFC => MbcType::PocketCamera,
+            0xFD => MbcType::BandaiTama5,
+            0xFE => MbcType::HuC3,
+            0xFF => MbcType::HuC1RamBattery,
+            _ => return Err(LoadError::UnknownCartType(code)),
+        })
+    }
+}
+
+enum Mapper {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+
+// see https://gbdev.io/pandocs/The_Cartridge_Header.html
+#[derive(Clone, Copy, Debug)]
+pub struct CartHeader {
+    title: [u8; 16],
+    pub manufacturer: [u8; 4],
+    pub cgb_flag: u8,
+    pub new_licensee: [u8; 2],
+    pub sgb_flag: u8,
+    pub cart_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub destination: u8,
+    pub old_licensee: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+
+    computed_checksum: u8, // header checksum as the boot ROM works it out
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    Enhanced, // runs on DMG as well
+    Only,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    TooSmall(usize),
+    TooLarge(usize),
+    UnknownCartType(u8),
+    UnsupportedCartType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    HeaderChecksum { expected: u8, actual: u8 },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::TooSmall(len) => write!(f, "{len} bytes is too small for a ROM"),
+            LoadError::TooLarge(len) => write!(f, "{len} bytes is too large for a ROM"),
+            LoadError::UnknownCartType(code) => write!(f, "unknown cartridge type {code:02X}"),
+            LoadError::UnsupportedCartType(code) => write!(f, "unsupported cartridge type {code:02X}"),
+            LoadError::UnknownRomSize(code) => write!(f, "unknown ROM size {code:02X}"),
+            LoadError::UnknownRamSize(code) => write!(f, "unknown RAM size {code:02X}"),
+            LoadError::HeaderChecksum { expected, actual } => {
+                write!(f, "header checksum is {actual:02X}, expected {expected:02X}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for LoadError {}
+
+impl LoadError {
+    // for hosts that only get a number back, 1 and up in declaration order
+    pub fn code(&self) -> i32 {
+        match self {
+            LoadError::TooSmall(_) => 1,
+            LoadError::TooLarge(_) => 2,
+            LoadError::UnknownCartType(_) => 3,
+            LoadError::UnsupportedCartType(_) => 4,
+            LoadError::UnknownRomSize(_) => 5,
+            LoadError::UnknownRamSize(_) => 6,
+            LoadError::HeaderChecksum { .. } => 7,
+        }
+    }
+}
+
+impl CartHeader {
+    pub const END: usize = 0x150;
+
+    // rom must cover the header, i.e. be at least CartHeader::END bytes
+    pub fn parse(rom: &[u8]) -> CartHeader {
+        let mut title = [0; 16];
+        // later carts give the end of the title to the manufacturer code and CGB flag
+        let title_len = if rom[0x143] & 0x80 != 0 { 15 } else { 16 };
+        title[..title_len].copy_from_slice(&rom[0x134..0x134 + title_len]);
+
+        CartHeader {
+            title,
+            manufacturer: rom[0x13F..0x143].try_into().unwrap(),
+            cgb_flag: rom[0x143],
+            new_licensee: rom[0x144..0x146].try_into().unwrap(),
+            sgb_flag: rom[0x146],
+            cart_type: rom[0x147],
+            rom_size: rom[0x148],
+            ram_size: rom[0x149],
+            destination: rom[0x14A],
+            old_licensee: rom[0x14B],
+            version: rom[0x14C],
+            header_checksum: rom[0x14D],
+            global_checksum: u16::from_be_bytes([rom[0x14E], rom[0x14F]]),
+
+            computed_checksum: rom[0x134..0x14D].iter().fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1)),
+        }
+    }
+
+    // up to the first NUL, anything but printable ASCII replaced
+    pub fn title(&self) -> impl Iterator<Item = char> + '_ {
+        self.title
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '?' })
+    }
+
+    pub fn title_bytes(&self) -> &[u8] {
+        let len = self.title.iter().position(|&b| b == 0).unwrap_or(self.title.len());
+        &self.title[..len]
+    }
+
+    pub fn cgb(&self) -> CgbSupport {
+        match self.cgb_flag {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        }
+    }
+
+    pub fn sgb(&self) -> bool {
+        // SGB functions also need the old licensee code to defer to the new one
+        self.sgb_flag == 0x03 && self.old_licensee == 0x33
+    }
+
+    // the two ASCII characters of the new code when the old one says so
+    pub fn licensee(&self) -> u16 {
+        if self.old_licensee == 0x33 {
+            u16::from_be_bytes(self.new_licensee)
+        } else {
+            self.old_licensee.into()
+        }
+    }
+
+    pub fn rom_banks(&self) -> Result<u16, LoadError> {
+        match self.rom_size {
+            code @ 0x00..=0x08 => Ok(2 << code),
+            // only ever seen in unofficial docs
+            0x52 => Ok(72),
+            0x53 => Ok(80),
+            0x54 => Ok(96),
+            code => Err(LoadError::UnknownRomSize(code)),
+        }
+    }
+
+    pub fn rom_bytes(&self) -> Result<usize, LoadError> {
+        Ok(self.rom_banks()? as usize * Cart::ROM_BANK_SIZE)
+    }
+
+    pub fn ram_banks(&self) -> Result<u8, LoadError> {
+        match self.ram_size {
+            0x00 => Ok(0),
+            0x01 => Ok(1), // 2 KiB, never used by a licensed cart
+            0x02 => Ok(1),
+            0x03 => Ok(4),
+            0x04 => Ok(16),
+            0x05 => Ok(8),
+            code => Err(LoadError::UnknownRamSize(code)),
+        }
+    }
+
+    // MBC2 RAM is built into the mapper and not declared here
+    pub fn ram_bytes(&self) -> Result<usize, LoadError> {
+        match self.ram_size {
+            0x01 => Ok(0x800),
+            _ => Ok(self.ram_banks()? as usize * Cart::RAM_BANK_SIZE),
+        }
+    }
+
+    // sum of every byte but the checksum itself
+    pub fn computed_global_checksum(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|&(i, _)| i != 0x14E && i != 0x14F)
+            .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b.into()))
+    }
+
+    // header checksum mismatches lock up the boot ROM, unlike the global
+    // checksum which nothing on the console ever looks at
+    pub fn verify(&self) -> Result<(), LoadError> {
+        if self.computed_checksum != self.header_checksum {
+            return Err(LoadError::HeaderChecksum {
+                expected: self.computed_checksum,
+                actual: self.header_checksum,
+            });
+        }
+        MbcType::decode(self.cart_type)?;
+        self.rom_banks()?;
+        self.ram_banks()?;
+        Ok(())
+    }
+
+    pub fn header_checksum_ok(&self) -> bool {
+        self.computed_checksum == self.header_checksum
+    }
+
+    pub fn global_checksum_ok(&self, rom: &[u8]) -> bool {
+        CartHeader::computed_global_checksum(rom) == self.global_checksum
+    }
+}
+
+// maps an offset past the end of an image the way missing address lines
+// do, e.g. the last 512 KiB of a 1.5 MiB image repeat to fill out 2 MiB
+fn mirror(mut addr: usize, mut size: usize) -> usize {
+    let mut base = 0;
+    let mut mask = 1 << (usize::BITS - 1);
+    while addr >= size {
+        while addr & mask == 0 {
+            mask >>= 1;
+        }
+        addr -= mask;
+        if size > mask {
+            size -= mask;
+            base += mask;
+        }
+        mask >>= 1;
+    }
+    base + addr
+}
+
+// the ROM image is allocated as each cart is inserted, already padded and
+// mirrored out to a power of two banks so that reads only mask the bank
 pub(crate) struct Cart {
-    pub rom_image: [u8; gb::MAX_CART_ROM_SIZE],
+    pub rom_image: Box<[u8]>,
 
     ram: [u8; 0x20000],
     ram_we: bool,
+    pub(crate) ram_dirty: bool, // battery RAM written since the host last saved it
 
-    pub bank2: u8,
-    pub bank4: u8,
+    pub bank2: u8, // register at 2000..3FFF
+    pub bank4: u8, // register at 4000..5FFF
+    bank3: u8,     // MBC5 ROM bank bit 8, register at 3000..3FFF
 
     mbc: MbcType,
-    rom_bank_limit: u8,
+    rom_bank_limit: u16,
     ram_bank_limit: u8,
+    ram_size: usize, // bytes of RAM on the cart, as declared by the header
 
     mbc1mode: bool,
+    mbc1m: bool, // multicart wiring, bank4 drives ROM A18..A19 instead of A19..A20
+
+    pub(crate) header: CartHeader,
+    rtc: Rtc,
+    pub(crate) rumble: bool, // motor state of rumble carts
 }
 
 impl Cart {
     const ROM_BANK_SIZE: usize = 0x4000;
     const RAM_BANK_SIZE: usize = 0x2000;
 
+    #[rustfmt::skip]
+    const LOGO: [u8; 0x30] = [
+        0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+        0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+        0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+    ];
+
     pub const fn init(&mut self) {
         self.mbc = MbcType::RomOnly;
         self.bank2 = 1;
+        self.rtc.init();
     }
 
-    fn parse_new_image(&mut self) {
-        self.mbc = match self.rom_image[0x147] {
-            0x03 => MbcType::Mbc1RamBattery,
-            _ => todo!("cart type {}", self.rom_image[0x147]),
-        };
+    // what reads back with no cart inserted
+    pub fn no_rom() -> Box<[u8]> {
+        vec![0; 2 * Cart::ROM_BANK_SIZE].into_boxed_slice()
+    }
+
+    // checks a ROM, pads it out to the size its header declares and
+    // inserts it; the cart is left untouched on errors
+    pub fn load_image(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        let len = data.len();
+        if len < CartHeader::END {
+            return Err(LoadError::TooSmall(len));
+        }
+        if len > gb::MAX_CART_ROM_SIZE {
+            return Err(LoadError::TooLarge(len));
+        }
+
+        let header = CartHeader::parse(data);
+        header.verify()?;
 
-        self.rom_bank_limit = match self.rom_image[0x148] {
-            0x04 => 0x40,
-            _ => todo!("cart rom bank {}", self.rom_image[0x148]),
+        let mbc = MbcType::decode(header.cart_type)?;
+        // the rest still have no mapper behind them
+        if matches!(
+            mbc,
+            MbcType::Mmm01
+                | MbcType::Mmm01Ram
+                | MbcType::Mmm01RamBattery
+                | MbcType::Mbc6
+                | MbcType::Mbc7SensorRumbleRamBattery
+                | MbcType::PocketCamera
+                | MbcType::BandaiTama5
+                | MbcType::HuC3
+        ) {
+            return Err(LoadError::UnsupportedCartType(header.cart_type));
+        }
+
+        // an image may also be larger than its header admits
+        let data_banks = len.div_ceil(Cart::ROM_BANK_SIZE);
+        let rom_banks = cmp::max(header.rom_banks()?.into(), data_banks);
+        let ram_banks = header.ram_banks()?;
+        let ram_size = match mbc {
+            MbcType::Mbc2 | MbcType::Mbc2Battery => 0x200,
+            _ => header.ram_bytes()?,
         };
 
-        self.ram_bank_limit = match self.rom_image[0x149] {
-            0x03 => 0x04,
-            _ => todo!("cart ram bank {}", self.rom_image[0x149]),
+        // erased flash reads FF, then missing address lines repeat the image
+        let padded = data_banks * Cart::ROM_BANK_SIZE;
+        let size = rom_banks.next_power_of_two() * Cart::ROM_BANK_SIZE;
+        let mut image = vec![0xFF; size];
+        image[..len].copy_from_slice(data);
+        for i in padded..size {
+            image[i] = image[mirror(i, padded)];
+        }
+
+        self.rom_image = image.into_boxed_slice();
+        self.mbc = mbc;
+        self.rom_bank_limit = rom_banks as u16;
+        self.ram_bank_limit = ram_banks;
+        self.ram_size = ram_size;
+        self.header = header;
+
+        // MBC1M carts repeat the boot logo at the start of each 256 KiB game
+        let game2 = 0x10 * Cart::ROM_BANK_SIZE;
+        self.mbc1m = matches!(self.mbc, MbcType::Mbc1 | MbcType::Mbc1Ram | MbcType::Mbc1RamBattery)
+            && self.rom_bank_limit == 64
+            && self.rom_image[game2 + 0x104..game2 + 0x134] == Cart::LOGO;
+
+        self.reset();
+        self.ram.fill(0);
+        self.ram_dirty = false;
+        self.rtc.reset();
+        Ok(())
+    }
+
+    // what a power cycle does to the mapper, RAM and clock keep their contents
+    pub fn reset(&mut self) {
+        self.ram_we = false;
+        self.bank2 = 1;
+        self.bank3 = 0;
+        self.bank4 = 0;
+        self.mbc1mode = false;
+        self.rumble = false;
+    }
+
+    // the banking logic each cart type is wired to, HuC1 banks close
+    // enough to MBC1 to share its implementation
+    const fn mapper(&self) -> Mapper {
+        match self.mbc {
+            MbcType::RomOnly | MbcType::RomRam | MbcType::RomRamBattery => Mapper::None,
+            MbcType::Mbc2 | MbcType::Mbc2Battery => Mapper::Mbc2,
+            MbcType::Mbc3TimerBattery
+            | MbcType::Mbc3TimerRamBattery
+            | MbcType::Mbc3
+            | MbcType::Mbc3Ram
+            | MbcType::Mbc3RamBattery => Mapper::Mbc3,
+            MbcType::Mbc5
+            | MbcType::Mbc5Ram
+            | MbcType::Mbc5RamBattery
+            | MbcType::Mbc5Rumble
+            | MbcType::Mbc5RumbleRam
+            | MbcType::Mbc5RumbleRamBattery => Mapper::Mbc5,
+            _ => Mapper::Mbc1,
+        }
+    }
+
+    const fn has_rtc(&self) -> bool {
+        matches!(self.mbc, MbcType::Mbc3TimerBattery | MbcType::Mbc3TimerRamBattery)
+    }
+
+    const fn has_rumble(&self) -> bool {
+        matches!(self.mbc, MbcType::Mbc5Rumble | MbcType::Mbc5RumbleRam | MbcType::Mbc5RumbleRamBattery)
+    }
+
+    const fn has_battery(&self) -> bool {
+        matches!(
+            self.mbc,
+            MbcType::Mbc1RamBattery
+                | MbcType::Mbc2Battery
+                | MbcType::RomRamBattery
+                | MbcType::Mmm01RamBattery
+                | MbcType::Mbc3TimerBattery
+                | MbcType::Mbc3TimerRamBattery
+                | MbcType::Mbc3RamBattery
+                | MbcType::Mbc5RamBattery
+                | MbcType::Mbc5RumbleRamBattery
+                | MbcType::Mbc7SensorRumbleRamBattery
+                | MbcType::HuC1RamBattery
+        )
+    }
+
+    // battery backed RAM in the raw .sav layout, empty without a battery
+    pub fn save_ram(&self) -> &[u8] {
+        let len = if self.has_battery() { self.ram_size } else { 0 };
+        &self.ram[..len]
+    }
+
+    pub fn save_ram_mut(&mut self) -> &mut [u8] {
+        let len = if self.has_battery() { self.ram_size } else { 0 };
+        &mut self.ram[..len]
+    }
+
+    // all RAM on the cart, battery or not
+    pub fn ram(&self) -> &[u8] {
+        &self.ram[..self.ram_size]
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram[..self.ram_size]
+    }
+
+    pub fn rtc(&self) -> Option<&Rtc> {
+        self.has_rtc().then_some(&self.rtc)
+    }
+
+    pub fn rtc_mut(&mut self) -> Option<&mut Rtc> {
+        self.has_rtc().then_some(&mut self.rtc)
+    }
+
+    // register writes that bring a freshly reset mapper into its current
+    // banking state
+    pub fn mbc_writes(&self) -> impl Iterator<Item = (bus::Addr, u8)> + Clone {
+        let ram_enable = if self.ram_we { 0x0A } else { 0x00 };
+        let (writes, len) = match self.mapper() {
+            Mapper::None => ([(0x0000, 0x00); 4], 0),
+            Mapper::Mbc1 => {
+                let mode = self.mbc1mode.into();
+                ([(0x0000, ram_enable), (0x2000, self.bank2), (0x4000, self.bank4), (0x6000, mode)], 4)
+            }
+            Mapper::Mbc2 => ([(0x0000, ram_enable), (0x0100, self.bank2), (0x0000, 0x00), (0x0000, 0x00)], 2),
+            Mapper::Mbc3 => ([(0x0000, ram_enable), (0x2000, self.bank2), (0x4000, self.bank4), (0x0000, 0x00)], 3),
+            Mapper::Mbc5 => ([(0x0000, ram_enable), (0x2000, self.bank2), (0x3000, self.bank3), (0x4000, self.bank4)], 4),
         };
+        writes.into_iter().take(len)
+    }
+
+    fn rom_bank_mask(&self) -> usize {
+        (self.rom_bank_limit.next_power_of_two() - 1).into()
+    }
+
+    fn bank4_shift(&self) -> u8 {
+        if self.mbc1m { 4 } else { 5 }
     }
 
     fn low_rom_bank(&self) -> usize {
-        if self.mbc1mode {
-            self.bank4 << 5
-        } else {
-            0
-        }.into()
+        let bank: usize = match self.mapper() {
+            Mapper::Mbc1 if self.mbc1mode => (self.bank4 << self.bank4_shift()).into(),
+            _ => 0,
+        };
+        bank & self.rom_bank_mask()
     }
 
     fn high_rom_bank(&self) -> usize {
-        (self.bank2 | self.bank4 << 5).into()
+        let bank: usize = match self.mapper() {
+            Mapper::None => 1,
+            Mapper::Mbc1 => {
+                // the zero check only looks at bank2, but MBC1M doesn't wire its top bit
+                let bank2 = if self.mbc1m { self.bank2 & 0x0F } else { self.bank2 };
+                (bank2 | self.bank4 << self.bank4_shift()).into()
+            }
+            Mapper::Mbc2 | Mapper::Mbc3 => self.bank2.into(),
+            Mapper::Mbc5 => usize::from(self.bank3) << 8 | usize::from(self.bank2),
+        };
+        bank & self.rom_bank_mask()
     }
 
     fn ram_bank(&self) -> usize {
-        if self.mbc1mode {
-            self.bank4 << 5
-        } else {
-            0
-        }.into()
+        let bank: usize = match self.mapper() {
+            Mapper::Mbc1 if self.mbc1mode => self.bank4.into(),
+            Mapper::Mbc3 => (self.bank4 & 0x07).into(),
+            // rumble carts drive the motor with bit 3 instead
+            Mapper::Mbc5 if self.has_rumble() => (self.bank4 & 0x07).into(),
+            Mapper::Mbc5 => self.bank4.into(),
+            _ => 0,
+        };
+        bank % cmp::max(self.ram_bank_limit, 1) as usize
+    }
+
+    // RTC register mapped at A000..BFFF instead of RAM, if any
+    fn rtc_reg(&self) -> Option<u8> {
+        (self.has_rtc() && matches!(self.bank4, 0x08..=0x0C)).then_some(self.bank4)
     }
 
     pub fn read_rom(&self, addr: bus::Addr) -> u8 {
         match addr {
-            0x0000..0x4000 => self.rom_image[self.low_rom_bank() * Cart::ROM_BANK_SIZE | addr as usize],
+            0x0000..0x4000 => self.rom_image[(self.low_rom_bank() * Cart::ROM_BANK_SIZE) | addr as usize],
             0x4000..0x8000 => self.rom_image[self.high_rom_bank() * Cart::ROM_BANK_SIZE + (addr - 0x4000) as usize],
             _ => unreachable!("{addr:04X}")
         }
     }
 
     pub fn write_rom(&mut self, addr: bus::Addr, val: u8) {
+        match self.mapper() {
+            Mapper::None => {}
+            Mapper::Mbc1 => self.write_mbc1(addr, val),
+            Mapper::Mbc2 => self.write_mbc2(addr, val),
+            Mapper::Mbc3 => self.write_mbc3(addr, val),
+            Mapper::Mbc5 => self.write_mbc5(addr, val),
+        }
+    }
+
+    fn write_mbc1(&mut self, addr: bus::Addr, val: u8) {
         match addr {
             0x0000..0x2000 => {
                 self.ram_we = val & 0x0F == 0xA;
@@ -122,15 +606,147 @@ impl Cart {
         }
     }
 
-    pub fn read_ram(&self, addr: bus::Addr) -> u8 {
+    // one register pair in 0000..3FFF, told apart by address bit 8
+    fn write_mbc2(&mut self, addr: bus::Addr, val: u8) {
+        match addr {
+            0x0000..0x4000 if addr & 0x0100 == 0 => {
+                self.ram_we = val & 0x0F == 0xA;
+            }
+            0x0000..0x4000 => {
+                self.bank2 = cmp::max(val & 0x0F, 1);
+            }
+            0x4000..0x8000 => {}
+            _ => unreachable!()
+        }
+    }
+
+    fn write_mbc3(&mut self, addr: bus::Addr, val: u8) {
+        match addr {
+            0x0000..0x2000 => {
+                self.ram_we = val & 0x0F == 0xA;
+            }
+            0x2000..0x4000 => {
+                self.bank2 = cmp::max(val & 0x7F, 1);
+            }
+            0x4000..0x6000 => {
+                self.bank4 = val & 0x0F;
+            }
+            0x6000..0x8000 => {
+                self.rtc.write_latch(val);
+            }
+            _ => unreachable!()
+        }
+    }
+
+    // unlike MBC1 and MBC3, bank 0 can be mapped at 4000..7FFF
+    fn write_mbc5(&mut self, addr: bus::Addr, val: u8) {
+        match addr {
+            0x0000..0x2000 => {
+                self.ram_we = val & 0x0F == 0xA;
+            }
+            0x2000..0x3000 => {
+                self.bank2 = val;
+            }
+            0x3000..0x4000 => {
+                self.bank3 = val & 0x01;
+            }
+            0x4000..0x6000 => {
+                self.bank4 = val & 0x0F;
+                if self.has_rumble() {
+                    self.rumble = val & 0x08 != 0;
+                }
+            }
+            0x6000..0x8000 => {}
+            _ => unreachable!()
+        }
+    }
+
+    fn ram_addr(&self, addr: bus::Addr) -> Option<usize> {
+        // without a mapper there is no enable register, the RAM is always on
+        let enabled = self.ram_we || matches!(self.mapper(), Mapper::None);
+        if !enabled {
+            return None;
+        }
+        // MBC2 has 512 half-bytes built in, echoed across the whole range
+        if let Mapper::Mbc2 = self.mapper() {
+            return Some((addr & 0x01FF).into());
+        }
+        if self.ram_bank_limit == 0 {
+            return None;
+        }
         let offset: usize = (addr - 0xA000).into();
-        self.ram[self.ram_bank() * Cart::RAM_BANK_SIZE + offset]
+        Some(self.ram_bank() * Cart::RAM_BANK_SIZE + offset)
+    }
+
+    pub fn read_ram(&self, addr: bus::Addr) -> u8 {
+        if let Some(reg) = self.rtc_reg() {
+            return if self.ram_we { self.rtc.read(reg) } else { 0xFF };
+        }
+        let val = self.ram_addr(addr).map_or(0xFF, |i| self.ram[i]);
+        match self.mapper() {
+            // only the lower nibble is wired, the rest floats high
+            Mapper::Mbc2 => val | 0xF0,
+            _ => val,
+        }
     }
 
     pub fn write_ram(&mut self, addr: bus::Addr, val: u8) {
-        if self.ram_we {
-            let offset: usize = (addr - 0xA000).into();
-            self.ram[self.ram_bank() * Cart::RAM_BANK_SIZE + offset] = val;
+        if let Some(reg) = self.rtc_reg() {
+            if self.ram_we {
+                self.rtc.write(reg, val);
+            }
+            return;
         }
+        if let Some(i) = self.ram_addr(addr) {
+            self.ram[i] = match self.mapper() {
+                Mapper::Mbc2 => val & 0x0F,
+                _ => val,
+            };
+            self.ram_dirty |= self.has_battery();
+        }
+    }
+
+    // save states only fit the ROM they were taken with
+    pub fn rom_id(&self) -> [u8; 19] {
+        let mut id = [0; 19];
+        id[..16].copy_from_slice(&self.header.title);
+        id[16] = self.header.header_checksum;
+        id[17..].copy_from_slice(&self.header.global_checksum.to_le_bytes());
+        id
+    }
+
+    pub fn tick(&mut self) {
+        if self.has_rtc() {
+            self.rtc.tick();
+        }
+    }
+}
+
+impl State for Cart {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&self.ram[..self.ram_size]);
+        w.bool(self.ram_we);
+        w.u8(self.bank2);
+        w.u8(self.bank3);
+        w.u8(self.bank4);
+        w.bool(self.mbc1mode);
+        w.bool(self.rumble);
+        self.rtc.save(w);
+    }
+
+    // banking registers are masked wherever they're used, so any value is safe
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        let size = self.ram_size;
+        r.bytes(&mut self.ram[..size])?;
+        self.ram_we = r.bool()?;
+        self.bank2 = r.u8()?;
+        self.bank3 = r.u8()?;
+        self.bank4 = r.u8()?;
+        self.mbc1mode = r.bool()?;
+        self.rumble = r.bool()?;
+        self.rtc.load(r)?;
+        // the loaded RAM most likely differs from the last .sav
+        self.ram_dirty = self.has_battery();
+        Ok(())
     }
 }
@@ -4,6 +4,7 @@ use core::ops::ControlFlow;
 
 use crate::bus;
 use crate::reg::Reg;
+use crate::state::{Reader, State, StateError, Writer};
 #[allow(unused_imports)]
 use crate::*;
 
@@ -79,6 +80,7 @@ impl RegId16 {
 #[derive(Debug, Clone, Copy)]
 enum OpdSrc {
     None,
+    Idle, /* internal M-cycle without bus access */
     Mem8(bus::Addr),
     Done8(u8),
     Mem8Ex(bus::Addr),
@@ -90,6 +92,7 @@ enum OpdSrc {
 
 #[derive(Debug, Clone, Copy)]
 enum OpdDst {
+    Idle, /* internal M-cycle without bus access */
     Mem8(bus::Addr, u8),
     Mem16(bus::Addr, u16),
     Mem16Half(bus::Addr, u8),
@@ -100,6 +103,7 @@ impl OpdSrc {
     pub fn read_step(&self, bus: &bus::Bus) -> OpdSrc {
         match self {
             OpdSrc::None | OpdSrc::Done8(_) | OpdSrc::Done16(_) | OpdSrc::Done8Ex(_) => *self,
+            OpdSrc::Idle => OpdSrc::None,
             OpdSrc::Mem8(addr) => OpdSrc::Done8(bus.read(*addr)),
             OpdSrc::Mem8Ex(addr) => OpdSrc::Done8Ex(bus.read(*addr)),
             OpdSrc::Mem16(addr) => OpdSrc::Mem16Half(*addr, bus.read(*addr)),
@@ -121,6 +125,7 @@ impl OpdDst {
     pub fn write_step(&self, bus: &mut bus::Bus) -> OpdDst {
         match self {
             OpdDst::Done => *self,
+            OpdDst::Idle => OpdDst::Done,
             OpdDst::Mem8(addr, val) => {
                 bus.write(*addr, *val);
                 OpdDst::Done
@@ -175,7 +180,11 @@ impl From<OpdSrc> for ReadVal {
             OpdSrc::Done8(val) => ReadVal::Done8(val),
             OpdSrc::Done8Ex(val) => ReadVal::Done8Ex(val),
             OpdSrc::Done16(val) => ReadVal::Done16(val),
-            OpdSrc::Mem8(_) | OpdSrc::Mem16(_) | OpdSrc::Mem16Half(_, _) | OpdSrc::Mem8Ex(_) => {
+            OpdSrc::Idle
+            | OpdSrc::Mem8(_)
+            | OpdSrc::Mem16(_)
+            | OpdSrc::Mem16Half(_, _)
+            | OpdSrc::Mem8Ex(_) => {
                 unreachable!("illegal conversion from OpdSrc to ReadVal")
             }
         }
@@ -206,6 +215,22 @@ enum Phase {
 }
 
 type InstOp = fn(&Cpu, Phase) -> Stage;
+
+// which table entry is executing, unlike the InstOp itself this can be saved
+#[derive(Clone, Copy)]
+enum InstRef {
+    Main(u8),
+    Prefixed(u8), // opcode >> 3
+}
+
+impl InstRef {
+    fn op(self) -> InstOp {
+        match self {
+            InstRef::Main(i) => inst::INST_TABLE[i as usize],
+            InstRef::Prefixed(i) => inst::PREFIX_INST_TABLE[i as usize],
+        }
+    }
+}
 mod inst {
     use super::cpu::*;
 
@@ -335,7 +360,7 @@ mod inst {
             Phase::InstFetch => decode_regind8_src(cpu, idx),
             Phase::ValueReady(src) => {
                 let shr = src.get8() << 1;
-                let c = (src.get8() & 0x01) << Cpu::CBIT;
+                let c = (src.get8() >> 7) << Cpu::CBIT;
                 let z = ((shr == 0) as u8) << Cpu::ZBIT;
                 cpu.f().set(z | c);
                 cpu.pc().inc(1);
@@ -366,9 +391,8 @@ mod inst {
         match phase {
             Phase::InstFetch => decode_regind8_src(cpu, idx),
             Phase::ValueReady(src) => {
-                let swap = cpu.a().get();
-                cpu.a().set(src.get8());
-                let z = ((src.get8() == 0) as u8) << Cpu::ZBIT;
+                let swap = src.get8().rotate_left(4);
+                let z = ((swap == 0) as u8) << Cpu::ZBIT;
                 cpu.f().set(z);
                 cpu.pc().inc(1);
                 decode_regind8_dst(cpu, idx, swap)
@@ -466,7 +490,7 @@ mod inst {
     fn alu(acc: u8, opd: u8, carry: bool) -> (u8, u8) {
         let (sum, c) = acc.carrying_add(opd, carry);
         let z = ((sum == 0) as u8) << Cpu::ZBIT;
-        let h = (((acc & 0x0F + opd & 0x0F) & 0x10) as u8) << Cpu::HBIT;
+        let h = ((((acc & 0x0F) + (opd & 0x0F) + carry as u8) & 0x10 != 0) as u8) << Cpu::HBIT;
         let c = (c as u8) << Cpu::CBIT;
         (sum, z | h | c)
     }
@@ -501,6 +525,12 @@ mod inst {
         }
     }
 
+    fn ldbb(cpu: &Cpu, phase: Phase) -> Stage {
+        // LD B, B; doubles as a software breakpoint for test ROMs
+        cpu.brk.set(cpu.brk_enable);
+        nop(cpu, phase)
+    }
+
     fn ld16(cpu: &Cpu, phase: Phase) -> Stage {
         // LD r16, n16
         match phase {
@@ -606,8 +636,16 @@ mod inst {
         }
     }
 
-    fn ldhac(cpu: &Cpu, _phase: Phase) -> Stage {
-        todo!("inst {:?}", cpu)
+    fn ldhac(cpu: &Cpu, phase: Phase) -> Stage {
+        // LDH A, [C]
+        match phase {
+            Phase::InstFetch => Stage::Read(OpdSrc::Mem8(cpu.c().get().as_hiaddr())),
+            Phase::ValueReady(src) => {
+                cpu.a().set(src.get8());
+                cpu.pc().inc(1);
+                Stage::Fetch
+            }
+        }
     }
 
     fn ldia(cpu: &Cpu, phase: Phase) -> Stage {
@@ -644,7 +682,7 @@ mod inst {
         // LD SP, HL
         cpu.pc().inc(1);
         cpu.sp().set(cpu.hl().get());
-        Stage::Write(OpdDst::Done)
+        Stage::Wait(OpdDst::Done)
     }
 
     fn offtsp(cpu: &Cpu, phase: Phase) -> Stage {
@@ -654,21 +692,22 @@ mod inst {
             Phase::ValueReady(src) => {
                 let opd1 = cpu.sp().get();
                 let opd2 = src.get8();
-                let (sum, c) = (opd1 as u8).carrying_add(opd2, false);
-                let (hi, _) = ((opd1 >> 8) as u8).carrying_add(0, c);
-                let h = ((((opd1 as u8) & 0x0F + opd2 & 0x0F) & 0x10 != 0) as u8) << Cpu::HBIT;
+                let sum = opd1.wrapping_add_signed(opd2.cast_signed().into());
+                // flags come from the unsigned add on the low byte
+                let (_, c) = (opd1 as u8).overflowing_add(opd2);
+                let h = (((((opd1 as u8) & 0x0F) + (opd2 & 0x0F)) & 0x10 != 0) as u8) << Cpu::HBIT;
                 let c = (c as u8) << Cpu::CBIT;
                 cpu.f().set(h | c);
                 cpu.pc().inc(1);
 
                 if cpu.opcode & 0x10 != 0 {
                     // LD HL, SP + e8
-                    cpu.hl().set((hi as u16) << 8 | sum as u16);
-                    Stage::Write(OpdDst::Done)
+                    cpu.hl().set(sum);
+                    Stage::Wait(OpdDst::Done)
                 } else {
                     // ADD SP, e8
-                    cpu.sp().set((hi as u16) << 8 | sum as u16);
-                    Stage::Wait(OpdDst::Done)
+                    cpu.sp().set(sum);
+                    Stage::Wait(OpdDst::Idle)
                 }
             }
         }
@@ -676,26 +715,28 @@ mod inst {
 
     fn inc16(cpu: &Cpu, phase: Phase) -> Stage {
         // INC r16
-        let idx = cpu.opcode >> 4 & 0x03;
+        let reg = cpu.r16(RegId16::decode(cpu.opcode >> 4 & 0x03));
 
         match phase {
-            Phase::InstFetch => decode_reg16_src(cpu, idx),
+            Phase::InstFetch => Stage::Read(OpdSrc::Done16(reg.get())),
             Phase::ValueReady(src) => {
                 cpu.pc().inc(1);
-                decode_reg16_dst(cpu, idx, src.get16().wrapping_add(1))
+                reg.set(src.get16().wrapping_add(1));
+                Stage::Wait(OpdDst::Done)
             }
         }
     }
 
     fn dec16(cpu: &Cpu, phase: Phase) -> Stage {
         // DEC r16
-        let idx = cpu.opcode >> 4 & 0x03;
+        let reg = cpu.r16(RegId16::decode(cpu.opcode >> 4 & 0x03));
 
         match phase {
-            Phase::InstFetch => decode_reg16_src(cpu, idx),
+            Phase::InstFetch => Stage::Read(OpdSrc::Done16(reg.get())),
             Phase::ValueReady(src) => {
                 cpu.pc().inc(1);
-                decode_reg16_dst(cpu, idx, src.get16().wrapping_sub(1))
+                reg.set(src.get16().wrapping_sub(1));
+                Stage::Wait(OpdDst::Done)
             }
         }
     }
@@ -707,7 +748,8 @@ mod inst {
             Phase::InstFetch => decode_regind8_src(cpu, idx),
             Phase::ValueReady(src) => {
                 let (inc, f) = alu(src.get8(), 1, false);
-                cpu.f().set(f);
+                let c = cpu.f().get() & (1 << Cpu::CBIT);
+                cpu.f().set((f & !(1 << Cpu::CBIT)) | c);
                 cpu.pc().inc(1);
                 decode_regind8_dst(cpu, idx, inc)
             }
@@ -721,7 +763,8 @@ mod inst {
             Phase::InstFetch => decode_regind8_src(cpu, idx),
             Phase::ValueReady(src) => {
                 let (dec, f) = alu(src.get8(), 1u8.wrapping_neg(), false);
-                cpu.f().set(negf(f));
+                let c = cpu.f().get() & (1 << Cpu::CBIT);
+                cpu.f().set((negf(f) & !(1 << Cpu::CBIT)) | c);
                 cpu.pc().inc(1);
                 decode_regind8_dst(cpu, idx, dec)
             }
@@ -739,8 +782,7 @@ mod inst {
             uc != 0
         } as u8)
             << Cpu::CBIT;
-        let z = ((rot == 0) as u8) << Cpu::ZBIT;
-        cpu.f().set(z | c);
+        cpu.f().set(c);
         cpu.pc().inc(1);
         cpu.a().set(rot);
         Stage::Fetch
@@ -757,8 +799,7 @@ mod inst {
             uc != 0
         } as u8)
             << Cpu::CBIT;
-        let z = ((rot == 0) as u8) << Cpu::ZBIT;
-        cpu.f().set(z | c);
+        cpu.f().set(c);
         cpu.pc().inc(1);
         cpu.a().set(rot);
         Stage::Fetch
@@ -775,7 +816,7 @@ mod inst {
     fn ccf(cpu: &Cpu, _phase: Phase) -> Stage {
         let f = cpu.f().get();
         let z = f & (1 << Cpu::ZBIT);
-        let c = !(f & (1 << Cpu::CBIT));
+        let c = !f & (1 << Cpu::CBIT);
         cpu.f().set(z | c);
         cpu.pc().inc(1);
         Stage::Fetch
@@ -783,15 +824,49 @@ mod inst {
 
     fn cpl(cpu: &Cpu, _phase: Phase) -> Stage {
         cpu.a().set(!cpu.a().get());
+        let zc = cpu.f().get() & (1 << Cpu::ZBIT | 1 << Cpu::CBIT);
         let n = 1 << Cpu::NBIT;
         let h = 1 << Cpu::HBIT;
-        cpu.f().set(n | h);
+        cpu.f().set(zc | n | h);
         cpu.pc().inc(1);
         Stage::Fetch
     }
 
     fn daa(cpu: &Cpu, _phase: Phase) -> Stage {
-        todo!("inst: {:?}", cpu)
+        // DAA
+        let f = cpu.f().get();
+        let n = f & (1 << Cpu::NBIT);
+        let mut c = f & (1 << Cpu::CBIT) != 0;
+        let h = f & (1 << Cpu::HBIT) != 0;
+
+        let a = cpu.a().get();
+        let mut adj = 0u8;
+        let adjusted = if n != 0 {
+            // after a subtraction only the flags tell which nibbles borrowed
+            if h {
+                adj |= 0x06;
+            }
+            if c {
+                adj |= 0x60;
+            }
+            a.wrapping_sub(adj)
+        } else {
+            if h || a & 0x0F > 0x09 {
+                adj |= 0x06;
+            }
+            if c || a > 0x99 {
+                adj |= 0x60;
+                c = true;
+            }
+            a.wrapping_add(adj)
+        };
+
+        cpu.a().set(adjusted);
+        let z = ((adjusted == 0) as u8) << Cpu::ZBIT;
+        let c = (c as u8) << Cpu::CBIT;
+        cpu.f().set(z | n | c);
+        cpu.pc().inc(1);
+        Stage::Fetch
     }
 
     fn jr(cpu: &Cpu, phase: Phase) -> Stage {
@@ -801,7 +876,7 @@ mod inst {
                 Stage::Read(OpdSrc::Mem8(cpu.pc().get()))
             }
             Phase::ValueReady(src) => {
-                cpu.pc().inc(src.get8().cast_signed() as u16 + 1);
+                cpu.pc().inc((src.get8().cast_signed() as u16).wrapping_add(1));
                 Stage::Wait(OpdDst::Done)
             }
         }
@@ -844,19 +919,17 @@ mod inst {
                 if cond(cpu.opcode, cpu.f().get()) {
                     jp(cpu, phase)
                 } else {
-                    cpu.pc().inc(1);
+                    cpu.pc().inc(2);
                     Stage::Fetch
                 }
             }
         }
     }
 
-    fn jphl(cpu: &Cpu, phase: Phase) -> Stage {
+    fn jphl(cpu: &Cpu, _phase: Phase) -> Stage {
         // JP HL
-        match phase {
-            Phase::InstFetch => Stage::Read(OpdSrc::Done16(cpu.hl().get())),
-            Phase::ValueReady(_) => jp(cpu, phase),
-        }
+        cpu.pc().set(cpu.hl().get());
+        Stage::Fetch
     }
 
     fn add(cpu: &Cpu, phase: Phase) -> Stage {
@@ -872,7 +945,7 @@ mod inst {
                 let (sum, c) = opd1.carrying_add(opd2, false);
                 cpu.a().set(sum);
                 let z = ((sum == 0) as u8) << Cpu::ZBIT;
-                let h = (((opd1 & 0x0F + opd2 & 0x0F) & 0x10 != 0) as u8) << Cpu::HBIT;
+                let h = ((((opd1 & 0x0F) + (opd2 & 0x0F)) & 0x10 != 0) as u8) << Cpu::HBIT;
                 let c = (c as u8) << Cpu::CBIT;
                 cpu.f().set(z | h | c);
                 cpu.pc().inc(1);
@@ -891,10 +964,11 @@ mod inst {
             Phase::ValueReady(src) => {
                 let opd1 = cpu.a().get();
                 let opd2 = src.get8();
-                let (sum, c) = opd1.carrying_add(opd2, (cpu.f().get() & (1 << Cpu::CBIT)) != 0);
+                let carry = (cpu.f().get() & (1 << Cpu::CBIT)) != 0;
+                let (sum, c) = opd1.carrying_add(opd2, carry);
                 cpu.a().set(sum);
                 let z = ((sum == 0) as u8) << Cpu::ZBIT;
-                let h = (((opd1 & 0x0F + opd2 & 0x0F) & 0x10 != 0) as u8) << Cpu::HBIT;
+                let h = ((((opd1 & 0x0F) + (opd2 & 0x0F) + carry as u8) & 0x10 != 0) as u8) << Cpu::HBIT;
                 let c = (c as u8) << Cpu::CBIT;
                 cpu.f().set(z | h | c);
                 cpu.pc().inc(1);
@@ -936,11 +1010,12 @@ mod inst {
             Phase::ValueReady(src) => {
                 let opd1 = cpu.a().get();
                 let opd2 = src.get8();
-                let (diff, c) = opd1.borrowing_sub(opd2, (cpu.f().get() & (1 << Cpu::CBIT)) != 0);
+                let borrow = (cpu.f().get() & (1 << Cpu::CBIT)) != 0;
+                let (diff, c) = opd1.borrowing_sub(opd2, borrow);
                 cpu.a().set(diff);
                 let z = ((diff == 0) as u8) << Cpu::ZBIT;
                 let n = 1u8 << Cpu::NBIT;
-                let h = ((opd1 & 0x0F < opd2 & 0x0F) as u8) << Cpu::HBIT;
+                let h = ((opd1 & 0x0F < (opd2 & 0x0F) + borrow as u8) as u8) << Cpu::HBIT;
                 let c = (c as u8) << Cpu::CBIT;
                 cpu.f().set(z | n | h | c);
                 cpu.pc().inc(1);
@@ -1031,18 +1106,18 @@ mod inst {
         let idx = cpu.opcode >> 4 & 0x03;
 
         match phase {
-            Phase::InstFetch => decode_reg16_src(cpu, idx),
+            Phase::InstFetch => Stage::Read(OpdSrc::Done16(cpu.r16(RegId16::decode(idx)).get())),
             Phase::ValueReady(src) => {
                 let opd1 = cpu.hl().get();
                 let opd2 = src.get16();
                 let (sum, c) = opd1.carrying_add(opd2, false);
                 cpu.hl().set(sum);
                 let z = cpu.f().get() & 1u8 << Cpu::ZBIT;
-                let h = (((opd1 & 0x0FFF + opd2 & 0x0FFF) & 0x1000 != 0) as u8) << Cpu::HBIT;
+                let h = ((((opd1 & 0x0FFF) + (opd2 & 0x0FFF)) & 0x1000 != 0) as u8) << Cpu::HBIT;
                 let c = (c as u8) << Cpu::CBIT;
                 cpu.f().set(z | h | c);
                 cpu.pc().inc(1);
-                Stage::Write(OpdDst::Done)
+                Stage::Wait(OpdDst::Done)
             }
         }
     }
@@ -1112,8 +1187,12 @@ mod inst {
     }
 
     fn rst(cpu: &Cpu, _phase: Phase) -> Stage {
-        cpu.intr((cpu.opcode & 0x38 >> 3).into());
-        Stage::Fetch
+        // RST vec
+        cpu.pc().inc(1);
+        Stage::Wait(OpdDst::Mem16(
+            cpu.sp().pre_dec(2),
+            cpu.pc().replace((cpu.opcode & 0x38).into()),
+        ))
     }
 
     fn ret(cpu: &Cpu, phase: Phase) -> Stage {
@@ -1134,15 +1213,17 @@ mod inst {
     fn retcc(cpu: &Cpu, phase: Phase) -> Stage {
         // RET cc
         match phase {
-            Phase::InstFetch => ret(cpu, phase),
-            Phase::ValueReady(_) => {
+            // condition is evaluated in its own M-cycle before the pop
+            Phase::InstFetch => Stage::Read(OpdSrc::Idle),
+            Phase::ValueReady(ReadVal::None) => {
                 if cond(cpu.opcode, cpu.f().get()) {
-                    ret(cpu, phase)
+                    ret(cpu, Phase::InstFetch)
                 } else {
                     cpu.pc().inc(1);
                     Stage::Fetch
                 }
             }
+            Phase::ValueReady(_) => ret(cpu, phase),
         }
     }
 
@@ -1184,7 +1265,9 @@ mod inst {
                 let idx = cpu.opcode >> 4 & 0x3;
                 cpu.pc().inc(1);
                 cpu.sp().inc(2);
-                decode_reg16_dst(cpu, idx, src.get16())
+                // low nibble of F is hardwired to 0
+                let val = if idx == 3 { src.get16() & 0xFFF0 } else { src.get16() };
+                decode_reg16_dst(cpu, idx, val)
             }
         }
     }
@@ -1224,7 +1307,13 @@ mod inst {
     }
 
     fn stop(cpu: &Cpu, _phase: Phase) -> Stage {
-        todo!("inst: {:?}", cpu)
+        // STOP n8
+        // whether this is a 1 or 2 byte instruction and which low power mode
+        // is entered depends on the joypad and pending interrupts, which are
+        // only visible from the bus; see Cpu::stop_step
+        cpu.pc().inc(1);
+        cpu.stop.set(StopStage::Init);
+        Stage::Fetch
     }
 
     /* PREFIX is inval because it is handled by outer fetch loop */
@@ -1244,7 +1333,7 @@ mod inst {
             jrcc,   ld16,   ldinda, inc16,  inc8,   dec8,   ld8imm, scf,
             jrcc,   addhl,  ldaind, dec16,  inc8,   dec8,   ld8imm, ccf,
             /* 0x4x */
-            ld8,    ld8,    ld8,    ld8,    ld8,    ld8,    ld8,    ld8,
+            ldbb,   ld8,    ld8,    ld8,    ld8,    ld8,    ld8,    ld8,
             ld8,    ld8,    ld8,    ld8,    ld8,    ld8,    ld8,    ld8,
             /* 0x5x */
             ld8,    ld8,    ld8,    ld8,    ld8,    ld8,    ld8,    ld8,
@@ -1288,29 +1377,48 @@ enum IntrStage {
     None,
     Init(u16),
     Wait(u16),
-    Exec(u16),
 }
 
 #[derive(Clone, Copy)]
 enum ImeSet {
     None,
     Init,
-    Wait,
+}
+
+#[derive(Clone, Copy)]
+enum StopStage {
+    None,
+    Init,
+    Stopped,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub af: u16,
+    pub bc: u16,
+    pub de: u16,
+    pub hl: u16,
+    pub sp: u16,
+    pub pc: u16,
 }
 
 pub struct Cpu {
     regs: [u16; 6],
     pub(self) ime: Cell<bool>, /* interrupt master enable */
-    pub(self) stop: Cell<bool>,
+    pub(self) stop: Cell<StopStage>,
     pub(self) halt: Cell<bool>,
 
     /* interrupt states */
     intr_stage: Cell<IntrStage>,
     pub(self) ime_enable: Cell<ImeSet>,
 
+    /* LD B, B software breakpoint */
+    pub(crate) brk_enable: bool,
+    brk: Cell<bool>,
+
     /* sub-instruction M-cycles state */
     pub(self) opcode: u8, /* executing opcode */
-    pub(self) instop: InstOp,
+    pub(self) inst: InstRef,
     stage: Stage,
 }
 
@@ -1338,14 +1446,15 @@ impl Cpu {
     pub const fn init(&mut self) {
         self.regs = [0; 6];
         self.ime = Cell::new(false);
-        self.stop = Cell::new(false);
+        self.stop = Cell::new(StopStage::None);
         self.halt = Cell::new(false);
         self.intr_stage = Cell::new(IntrStage::None);
         self.ime_enable = Cell::new(ImeSet::None);
+        self.brk = Cell::new(false);
 
         self.opcode = 0;
         self.stage = Stage::Fetch;
-        self.instop = inst::INST_TABLE[0];
+        self.inst = InstRef::Main(0x00);
     }
 
     const ZBIT: u8 = 7;
@@ -1371,11 +1480,66 @@ impl Cpu {
         })
     }
 
+    // whether LD B, B was hit since the last call
+    pub fn take_brk(&self) -> bool {
+        self.brk.replace(false)
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            af: self.af().get(),
+            bc: self.bc().get(),
+            de: self.de().get(),
+            hl: self.hl().get(),
+            sp: self.sp().get(),
+            pc: self.pc().get(),
+        }
+    }
+
+    pub fn set_registers(&self, regs: Registers) {
+        self.af().set(regs.af);
+        self.bc().set(regs.bc);
+        self.de().set(regs.de);
+        self.hl().set(regs.hl);
+        self.sp().set(regs.sp);
+        self.pc().set(regs.pc);
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime.get()
+    }
+
+    pub fn set_ime(&self, ime: bool) {
+        self.ime.set(ime);
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halt.get()
+    }
+
+    pub fn set_halted(&self, halt: bool) {
+        self.halt.set(halt);
+    }
+
+    pub fn stopped(&self) -> bool {
+        matches!(self.stop.get(), StopStage::Stopped)
+    }
+
+    pub fn set_stopped(&self, stop: bool) {
+        self.stop.set(if stop { StopStage::Stopped } else { StopStage::None });
+    }
+
+    // called while an enabled interrupt is pending, returns if it is serviced
     pub fn intr(&self, addr: bus::Addr) -> bool {
-        if self.ime.get() {
-            if self.halt.get() {
-                self.halt.set(false);
-            }
+        // HALT ends on a pending interrupt even with IME cleared
+        self.halt.set(false);
+
+        // only taken between instructions
+        if self.ime.get()
+            && matches!(self.stage, Stage::Fetch)
+            && matches!(self.intr_stage.get(), IntrStage::None)
+        {
+            self.ime.set(false);
             self.intr_stage.set(IntrStage::Init(addr));
             true
         } else {
@@ -1384,36 +1548,62 @@ impl Cpu {
     }
 
     fn intr_step(&mut self) -> ControlFlow<()> {
-        self.ime_enable.set(match self.ime_enable.get() {
-            ImeSet::None => ImeSet::None,
-            ImeSet::Init => ImeSet::Wait,
-            ImeSet::Wait => {
-                self.ime.set(true);
-                ImeSet::None
-            }
-        });
+        // EI takes effect after the following instruction
+        if let ImeSet::Init = self.ime_enable.replace(ImeSet::None) {
+            self.ime.set(true);
+        }
 
-        let old_stage = self.intr_stage.get();
-        self.intr_stage.set(match old_stage {
-            IntrStage::None => IntrStage::None,
-            IntrStage::Init(addr) => IntrStage::Wait(addr),
-            IntrStage::Wait(addr) => IntrStage::Exec(addr),
-            IntrStage::Exec(addr) => {
+        // dispatch is 5 M-cycles: 2 idle, then a CALL without operand fetch
+        match self.intr_stage.get() {
+            IntrStage::None => ControlFlow::Continue(()),
+            IntrStage::Init(addr) => {
+                self.intr_stage.set(IntrStage::Wait(addr));
+                ControlFlow::Break(())
+            }
+            IntrStage::Wait(addr) => {
                 // println!("intr exec {:04X} to {addr:04X}", self.pc().get());
                 self.stage = Stage::Read(OpdSrc::Done16(addr));
-                self.instop = inst::INST_TABLE[0xCD]; // call
-                IntrStage::None
+                self.inst = InstRef::Main(0xCD); // call
+                self.intr_stage.set(IntrStage::None);
+                ControlFlow::Break(())
             }
-        });
-        match old_stage {
-            IntrStage::None => {
-                if matches!(self.intr_stage.get(), IntrStage::None) {
-                    ControlFlow::Continue(())
+        }
+    }
+
+    // see https://gbdev.io/pandocs/Reducing_Power_Consumption.html#using-the-stop-instruction
+    fn stop_step(&mut self, bus: &mut bus::Bus) -> ControlFlow<()> {
+        let pressed = bus.read_joystate() & 0x0F != 0x0F;
+
+        self.stop.set(match self.stop.get() {
+            StopStage::None => StopStage::None,
+            StopStage::Init => {
+                let pending = bus.intr.pending() != 0;
+                if !pending {
+                    // the byte after STOP is skipped
+                    self.pc().inc(1);
+                }
+
+                if pressed {
+                    if !pending {
+                        self.halt.set(true);
+                    }
+                    StopStage::None
                 } else {
-                    ControlFlow::Break(())
+                    // the system counter stops at 0 and the LCD goes blank
+                    // until a button press, see GB::tick
+                    bus.write(0xFF04, 0x00);
+                    bus.ppu.blank();
+                    StopStage::Stopped
                 }
             }
-            _ => ControlFlow::Break(()),
+            StopStage::Stopped if pressed => StopStage::None,
+            StopStage::Stopped => StopStage::Stopped,
+        });
+
+        if matches!(self.stop.get(), StopStage::Stopped) {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
         }
     }
 
@@ -1422,7 +1612,7 @@ impl Cpu {
      * one M-cycle can only have at most 1 bus read/write
      */
     pub fn tick(&mut self, bus: &mut bus::Bus) {
-        if self.stop.get() || self.halt.get() {
+        if matches!(self.stop_step(bus), ControlFlow::Break(_)) || self.halt.get() {
             return;
         }
 
@@ -1439,16 +1629,16 @@ impl Cpu {
                         self.pc().inc(1);
                         Stage::FetchPrefixed
                     } else {
-                        self.instop = inst::INST_TABLE[self.opcode as usize];
-                        (self.instop)(&self, Phase::InstFetch)
+                        self.inst = InstRef::Main(self.opcode);
+                        self.inst.op()(self, Phase::InstFetch)
                     }
                 }
             }
             Stage::FetchPrefixed => {
                 self.opcode = bus.read(self.pc().get());
                 memop = true;
-                self.instop = inst::PREFIX_INST_TABLE[(self.opcode >> 3) as usize];
-                (self.instop)(&self, Phase::InstFetch)
+                self.inst = InstRef::Prefixed(self.opcode >> 3);
+                self.inst.op()(self, Phase::InstFetch)
             }
             Stage::Read(_) | Stage::Write(_) => self.stage,
             Stage::Wait(dst) => {
@@ -1457,27 +1647,206 @@ impl Cpu {
             }
         };
 
-        if let Stage::Read(src) = self.stage {
-            if src.ready() || !memop {
-                let src = src.read_step(bus);
-                memop = true;
-                self.stage = if src.ready() {
-                    (self.instop)(&self, Phase::ValueReady(src.into()))
-                } else {
-                    Stage::Read(src)
-                }
+        if let Stage::Read(src) = self.stage
+            && (src.ready() || !memop)
+        {
+            let src = src.read_step(bus);
+            memop = true;
+            self.stage = if src.ready() {
+                self.inst.op()(self, Phase::ValueReady(src.into()))
+            } else {
+                Stage::Read(src)
             }
         }
 
-        if let Stage::Write(dst) = self.stage {
-            if dst.ready() || !memop {
-                let dst = dst.write_step(bus);
-                self.stage = if dst.ready() {
-                    Stage::Fetch
-                } else {
-                    Stage::Write(dst)
-                }
+        if let Stage::Write(dst) = self.stage
+            && (dst.ready() || !memop)
+        {
+            let dst = dst.write_step(bus);
+            self.stage = if dst.ready() {
+                Stage::Fetch
+            } else {
+                Stage::Write(dst)
             }
         };
     }
 }
+
+// every enum goes out as a tag byte and a fixed size payload
+impl OpdSrc {
+    fn save(&self, w: &mut Writer) {
+        let (tag, a, b) = match *self {
+            OpdSrc::None => (0, 0, 0),
+            OpdSrc::Idle => (1, 0, 0),
+            OpdSrc::Mem8(addr) => (2, addr, 0),
+            OpdSrc::Done8(val) => (3, 0, val),
+            OpdSrc::Mem8Ex(addr) => (4, addr, 0),
+            OpdSrc::Done8Ex(val) => (5, 0, val),
+            OpdSrc::Mem16(addr) => (6, addr, 0),
+            OpdSrc::Mem16Half(addr, lo) => (7, addr, lo),
+            OpdSrc::Done16(val) => (8, val, 0),
+        };
+        w.u8(tag);
+        w.u16(a);
+        w.u16(b.into());
+    }
+
+    fn load(r: &mut Reader) -> Result<OpdSrc, StateError> {
+        let (tag, a, b) = (r.u8()?, r.u16()?, r.u16()? as u8);
+        Ok(match tag {
+            0 => OpdSrc::None,
+            1 => OpdSrc::Idle,
+            2 => OpdSrc::Mem8(a),
+            3 => OpdSrc::Done8(b),
+            4 => OpdSrc::Mem8Ex(a),
+            5 => OpdSrc::Done8Ex(b),
+            6 => OpdSrc::Mem16(a),
+            7 => OpdSrc::Mem16Half(a, b),
+            8 => OpdSrc::Done16(a),
+            _ => return Err(StateError::Invalid),
+        })
+    }
+}
+
+impl OpdDst {
+    fn save(&self, w: &mut Writer) {
+        let (tag, a, b) = match *self {
+            OpdDst::Idle => (0, 0, 0),
+            OpdDst::Mem8(addr, val) => (1, addr, val.into()),
+            OpdDst::Mem16(addr, val) => (2, addr, val),
+            OpdDst::Mem16Half(addr, hi) => (3, addr, hi.into()),
+            OpdDst::Done => (4, 0, 0),
+        };
+        w.u8(tag);
+        w.u16(a);
+        w.u16(b);
+    }
+
+    fn load(r: &mut Reader) -> Result<OpdDst, StateError> {
+        let (tag, a, b) = (r.u8()?, r.u16()?, r.u16()?);
+        Ok(match tag {
+            0 => OpdDst::Idle,
+            1 => OpdDst::Mem8(a, b as u8),
+            2 => OpdDst::Mem16(a, b),
+            3 => OpdDst::Mem16Half(a, b as u8),
+            4 => OpdDst::Done,
+            _ => return Err(StateError::Invalid),
+        })
+    }
+}
+
+impl Stage {
+    // both operand kinds take five bytes, fetches are padded to match
+    const PAYLOAD: usize = 5;
+
+    fn save(&self, w: &mut Writer) {
+        match self {
+            Stage::Fetch => {
+                w.u8(0);
+                w.bytes(&[0; Stage::PAYLOAD]);
+            }
+            Stage::FetchPrefixed => {
+                w.u8(1);
+                w.bytes(&[0; Stage::PAYLOAD]);
+            }
+            Stage::Read(src) => {
+                w.u8(2);
+                src.save(w);
+            }
+            Stage::Wait(dst) => {
+                w.u8(3);
+                dst.save(w);
+            }
+            Stage::Write(dst) => {
+                w.u8(4);
+                dst.save(w);
+            }
+        }
+    }
+
+    fn load(r: &mut Reader) -> Result<Stage, StateError> {
+        Ok(match r.u8()? {
+            0 => {
+                r.take(Stage::PAYLOAD)?;
+                Stage::Fetch
+            }
+            1 => {
+                r.take(Stage::PAYLOAD)?;
+                Stage::FetchPrefixed
+            }
+            2 => Stage::Read(OpdSrc::load(r)?),
+            3 => Stage::Wait(OpdDst::load(r)?),
+            4 => Stage::Write(OpdDst::load(r)?),
+            _ => return Err(StateError::Invalid),
+        })
+    }
+}
+
+impl State for Cpu {
+    fn save(&self, w: &mut Writer) {
+        for reg in self.regs {
+            w.u16(reg);
+        }
+        w.bool(self.ime.get());
+        w.u8(match self.stop.get() {
+            StopStage::None => 0,
+            StopStage::Init => 1,
+            StopStage::Stopped => 2,
+        });
+        w.bool(self.halt.get());
+
+        let (tag, addr) = match self.intr_stage.get() {
+            IntrStage::None => (0, 0),
+            IntrStage::Init(addr) => (1, addr),
+            IntrStage::Wait(addr) => (2, addr),
+        };
+        w.u8(tag);
+        w.u16(addr);
+        w.bool(matches!(self.ime_enable.get(), ImeSet::Init));
+
+        w.u8(self.opcode);
+        match self.inst {
+            InstRef::Main(i) => {
+                w.u8(0);
+                w.u8(i);
+            }
+            InstRef::Prefixed(i) => {
+                w.u8(1);
+                w.u8(i);
+            }
+        }
+        self.stage.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        for reg in &mut self.regs {
+            *reg = r.u16()?;
+        }
+        self.ime.set(r.bool()?);
+        self.stop.set(match r.u8()? {
+            0 => StopStage::None,
+            1 => StopStage::Init,
+            2 => StopStage::Stopped,
+            _ => return Err(StateError::Invalid),
+        });
+        self.halt.set(r.bool()?);
+
+        let (tag, addr) = (r.u8()?, r.u16()?);
+        self.intr_stage.set(match tag {
+            0 => IntrStage::None,
+            1 => IntrStage::Init(addr),
+            2 => IntrStage::Wait(addr),
+            _ => return Err(StateError::Invalid),
+        });
+        self.ime_enable.set(if r.bool()? { ImeSet::Init } else { ImeSet::None });
+
+        self.opcode = r.u8()?;
+        self.inst = match (r.u8()?, r.u8()?) {
+            (0, i) => InstRef::Main(i),
+            (1, i) if (i as usize) < inst::PREFIX_INST_TABLE.len() => InstRef::Prefixed(i),
+            _ => return Err(StateError::Invalid),
+        };
+        self.stage = Stage::load(r)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use crate::state::{Reader, State, StateError, Writer};
+
+// OAM DMA, copying a page into OAM one byte per M-cycle
+// see https://gbdev.io/pandocs/OAM_DMA_Transfer.html
+//
+// the transfer has the bus to itself while it runs, so the CPU only gets
+// at FF00 and up: the registers and HRAM
+pub(crate) struct Dma {
+    pub reg: u8, // FF46, the last page written
+
+    start: u8,     // M-cycles until a requested transfer takes over
+    active: bool,
+    src: u16,      // page being copied
+    pos: u8,       // bytes copied so far
+}
+
+impl Dma {
+    const LEN: u8 = 0xA0;
+    // the M-cycle of the write and one more to set up
+    const START_CYCLES: u8 = 2;
+
+    pub const fn init(&mut self) {
+        self.reg = 0xFF;
+    }
+
+    pub fn write(&mut self, val: u8) {
+        self.reg = val;
+        // a transfer already running carries on until this one starts
+        self.start = Dma::START_CYCLES;
+    }
+
+    // whether the CPU finds the bus taken at addr
+    pub fn blocks(&self, addr: u16) -> bool {
+        self.active && addr < 0xFF00
+    }
+
+    // the byte to copy this M-cycle as source and OAM offset, if any
+    pub fn tick(&mut self) -> Option<(u16, u8)> {
+        let copy = if self.active {
+            let pos = self.pos;
+            self.pos += 1;
+            self.active = self.pos < Dma::LEN;
+            Some((self.src | pos as u16, pos))
+        } else {
+            None
+        };
+
+        if self.start > 0 {
+            self.start -= 1;
+            if self.start == 0 {
+                // E000 and up is read from WRAM, like echo RAM
+                self.src = if self.reg >= 0xE0 { self.reg - 0x20 } else { self.reg } as u16 * 0x100;
+                self.pos = 0;
+                self.active = true;
+            }
+        }
+        copy
+    }
+}
+
+impl State for Dma {
+    fn save(&self, w: &mut Writer) {
+        w.u8(self.reg);
+        w.u8(self.start);
+        w.bool(self.active);
+        w.u16(self.src);
+        w.u8(self.pos);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.reg = r.u8()?;
+        self.start = r.u8()?;
+        self.active = r.bool()?;
+        self.src = r.u16()?;
+        self.pos = r.u8()?;
+        if self.start > Dma::START_CYCLES || self.pos > Dma::LEN || self.src & 0xFF != 0 || self.src >= 0xE000 {
+            return Err(StateError::Invalid);
+        }
+        Ok(())
+    }
+}
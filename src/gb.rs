@@ -1,38 +1,316 @@
+use core::alloc::Layout;
 use core::ops::ControlFlow;
+use core::ptr;
 
+use alloc::alloc::{alloc_zeroed, handle_alloc_error};
+use alloc::boxed::Box;
+use alloc::vec;
+
+use crate::bess::{self, Bess};
+use crate::boot::{self, Model};
 use crate::bus::Bus;
-use crate::cpu::Cpu;
+use crate::cart::{Cart, CartHeader, LoadError};
+use crate::cpu::{Cpu, Registers};
+use crate::graphic::Renderer;
 use crate::intr::IntrSrc;
+use crate::rtc::RTC_FOOTER_LEN;
+use crate::state::{self, Reader, State, StateError, Writer};
 
 use crate::*;
 
-pub(crate) const FRAME_WIDTH: usize = 160;
-pub(crate) const FRAME_HEIGHT: usize = 144;
+pub const FRAME_WIDTH: usize = 160;
+pub const FRAME_HEIGHT: usize = 144;
 // FRAME_BUFFER has data in 8-bit RGBA format
 // thus buffersize is pixel count time 4
 // chosen to better interop with WebAPI ImageData
 // which assumes 8-bit RGBA in Uint8ClampedArray
-pub(crate) const FRAME_BUFFER_SIZE: usize = gb::FRAME_WIDTH * gb::FRAME_HEIGHT * 4;
+pub const FRAME_BUFFER_SIZE: usize = gb::FRAME_WIDTH * gb::FRAME_HEIGHT * 4;
 
 pub(crate) const MAX_CART_ROM_SIZE: usize = 0x800000;
 
+// 154 lines of 456 dots, 4 dots per M-cycle
+pub const CYCLES_PER_FRAME: usize = 154 * 456 / 4;
+
 pub struct GB {
     pub(crate) bus: Bus,
     pub(crate) cpu: Cpu,
     tick: u128,
 
     pub(crate) paused: bool,
+
+    model: Model,
+    bootrom: bool, // whether a boot ROM was supplied to run at reset
 }
 
 impl GB {
+    // GB is far too large for the stack (the cart RAM alone is 128 KiB),
+    // so it is always built in place on the heap; zeros are a valid start
+    // for all of it but the ROM image pointer
+    pub fn new() -> Box<GB> {
+        let layout = Layout::new::<GB>();
+        unsafe {
+            let gb_ptr = alloc_zeroed(layout) as *mut GB;
+            if gb_ptr.is_null() {
+                handle_alloc_error(layout);
+            }
+            ptr::write(&raw mut (*gb_ptr).bus.cart.rom_image, Cart::no_rom());
+            (*gb_ptr).init();
+            Box::from_raw(gb_ptr)
+        }
+    }
+
+    pub fn frame_buffer(&self) -> &[u8; FRAME_BUFFER_SIZE] {
+        &self.bus.ppu.frame_buffer
+    }
+
+    // a DMG, MGB or SGB boot ROM, which runs from 0000 after the reset
+    // this triggers; CGB boot ROMs need CGB hardware and won't work
+    pub fn load_bootrom(&mut self, data: &[u8]) {
+        let len = data.len().min(self.bus.bootrom.len());
+        self.bus.bootrom[..len].copy_from_slice(&data[..len]);
+        self.bootrom = true;
+        self.reset();
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // takes effect from the reset this triggers
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.reset();
+    }
+
+    // insert a cart and power cycle, any boot ROM is kept
+    pub fn load_rom(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        self.bus.cart.load_image(data)?;
+        self.reset();
+        Ok(())
+    }
+
+    // power cycle, keeping the boot ROM, the cart and its RAM, and the
+    // LD B, B breakpoint setting
+    pub fn reset(&mut self) {
+        let brk_enable = self.cpu.brk_enable;
+        unsafe {
+            ptr::write_bytes(&raw mut self.cpu, 0, 1);
+        }
+        self.cpu.init();
+        self.cpu.brk_enable = brk_enable;
+        self.bus.reset();
+        self.boot();
+    }
+
+    pub fn renderer(&self) -> Renderer {
+        self.bus.ppu.renderer
+    }
+
+    // can be switched at any time, a line being drawn may come out blank
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.bus.ppu.renderer = renderer;
+    }
+
+    pub fn header(&self) -> &CartHeader {
+        &self.bus.cart.header
+    }
+
+    // byte most recently shifted out of the serial port, if not yet taken
+    pub fn take_serial(&mut self) -> Option<u8> {
+        self.bus.serial.out.take()
+    }
+
+    // the buttons held, one bit per button and 0 while pressed: right,
+    // left, up, down from bit 0, then A, B, select and start; a button
+    // going down on a selected line requests the joypad interrupt
+    pub fn set_buttons(&mut self, state: u8) {
+        let previous_matrix = self.bus.read_joystate();
+        self.bus.joy_state = state;
+        let current_matrix = self.bus.read_joystate();
+
+        if previous_matrix & !current_matrix & 0x0F != 0 {
+            self.bus.intr.raise(IntrSrc::Joypad);
+        }
+    }
+
+    // stop GB::tick at LD B, B, as the mooneye test ROMs expect
+    pub fn set_ld_b_b_break(&mut self, enable: bool) {
+        self.cpu.brk_enable = enable;
+    }
+
+    // battery backed cart RAM as a raw .sav image, empty if the cart has none
+    pub fn save_ram(&self) -> &[u8] {
+        self.bus.cart.save_ram()
+    }
+
+    // restores a .sav image, a short one only overwrites the start of RAM
+    pub fn load_save_ram(&mut self, data: &[u8]) {
+        let ram = self.bus.cart.save_ram_mut();
+        let len = data.len().min(ram.len());
+        ram[..len].copy_from_slice(&data[..len]);
+        self.bus.cart.ram_dirty = false;
+    }
+
+    // the clock of carts with a timer as the footer that follows the RAM in
+    // a .sav file, stamped with now, the Unix time; None for other carts
+    pub fn save_rtc(&self, now: u64) -> Option<[u8; RTC_FOOTER_LEN]> {
+        self.bus.cart.rtc().map(|rtc| rtc.footer(now))
+    }
+
+    // restores a footer from save_rtc, catching up with the time since it
+    // was saved; ignored unless the cart has a clock and the footer is
+    // RTC_FOOTER_LEN bytes long
+    pub fn load_rtc(&mut self, footer: &[u8], now: u64) {
+        if let (Some(rtc), Ok(footer)) = (self.bus.cart.rtc_mut(), footer.try_into()) {
+            rtc.set_footer(footer, now);
+        }
+    }
+
+    // whether the game wrote to save RAM since the last call
+    pub fn take_save_ram_dirty(&mut self) -> bool {
+        core::mem::take(&mut self.bus.cart.ram_dirty)
+    }
+
+    // while the game has the LCD off the frame buffer is blank, which the
+    // host may want to tell apart from a white screen
+    pub fn lcd_off(&self) -> bool {
+        !self.bus.ppu.lcd_on()
+    }
+
+    // whether the rumble motor of the cart is currently on
+    pub fn rumble(&self) -> bool {
+        self.bus.cart.rumble
+    }
+
+    // snapshot of the whole machine into buf, returning its length; the
+    // boot ROM, cart ROM and host settings are not part of it
+    pub fn save_state(&self, buf: &mut [u8]) -> Result<usize, StateError> {
+        let cap = buf.len();
+        let mut w = Writer::new(buf);
+        self.save(&mut w);
+        if w.len() > cap {
+            return Err(StateError::BufferTooSmall(w.len()));
+        }
+        Ok(w.len())
+    }
+
+    // exact size save_state needs for the current cart
+    pub fn state_len(&self) -> usize {
+        let mut w = Writer::new(&mut []);
+        self.save(&mut w);
+        w.len()
+    }
+
+    // restores a snapshot taken with the same ROM inserted, leaving the
+    // machine untouched on errors; a corrupt body only shows partway
+    // through loading it, so the machine is saved first and put back
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        self.load_state_with(data, &mut vec![0; self.state_len()])
+    }
+
+    // load_state saving the machine to backup, which takes a whole state,
+    // rather than to a buffer of its own
+    pub(crate) fn load_state_with(&mut self, data: &[u8], backup: &mut [u8]) -> Result<(), StateError> {
+        let expected = self.state_len();
+        let mut r = Reader::new(data);
+        state::load_header(&mut r, &self.bus.cart.rom_id())?;
+        if data.len() != expected {
+            return Err(StateError::Length { expected, actual: data.len() });
+        }
+
+        let backup = backup.get_mut(..expected).ok_or(StateError::BufferTooSmall(expected))?;
+        self.save(&mut Writer::new(backup));
+        let result = self.load(&mut Reader::new(data));
+        if result.is_err() {
+            self.load(&mut Reader::new(backup)).expect("a state just taken loads");
+        }
+        result
+    }
+
+    // the machine in the BESS format other emulators read, see bess.rs;
+    // it always fits STATE_MAX_SIZE. now is the Unix time, which the cart
+    // clock is stamped with
+    pub fn save_bess(&self, buf: &mut [u8], now: u64) -> Result<usize, StateError> {
+        let cap = buf.len();
+        let mut w = Writer::new(buf);
+        bess::save(&mut w, &self.cpu, &self.bus, self.model, now);
+        if w.len() > cap {
+            return Err(StateError::BufferTooSmall(w.len()));
+        }
+        Ok(w.len())
+    }
+
+    // a BESS state from any emulator, taken with the same ROM inserted if
+    // it says which; the model switches to that of the state, and the
+    // machine is left untouched on errors. the cart clock catches up with
+    // the time since the state was saved, now being the Unix time
+    pub fn load_bess(&mut self, data: &[u8], now: u64) -> Result<(), StateError> {
+        let bess = Bess::parse(data, &self.bus.cart.rom_image)?;
+        self.model = bess.model();
+        self.reset();
+        bess.apply(&self.cpu, &mut self.bus, self.bootrom, now);
+        Ok(())
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    pub fn vram(&self) -> &[u8; 0x2000] {
+        &self.bus.ppu.vram
+    }
+
     pub fn init(&mut self) {
         self.cpu.init();
         self.bus.init();
+        self.model = Model::Dmg;
+        self.boot();
+    }
+
+    fn boot(&mut self) {
         self.tick = 0;
         self.paused = false;
 
-        self.bus.boot_map = false;
-        self.cpu.pc().set(0x0100);
+        self.bus.boot_map = self.bootrom;
+        if self.bootrom {
+            self.cpu.pc().set(0x0000);
+        } else {
+            self.skip_bootrom();
+        }
+    }
+
+    // leave everything the way the boot ROM of the model would
+    fn skip_bootrom(&mut self) {
+        let post = self.model.post_boot(self.bus.cart.header.header_checksum);
+        self.cpu.set_registers(post.regs);
+        self.bus.timer.set_counter(post.div);
+
+        let bus = &mut self.bus;
+        bus.joy_sel = 0x00;
+        bus.serial.sc = post.sc;
+        bus.intr.write_if(0x01);
+
+        #[rustfmt::skip]
+        let apu = [
+            (0xFF10, 0x80), (0xFF11, 0xBF), (0xFF12, 0xF3), (0xFF13, 0xFF), (0xFF14, 0xBF),
+            (0xFF16, 0x3F), (0xFF17, 0x00), (0xFF18, 0xFF), (0xFF19, 0xBF),
+            (0xFF1A, 0x7F), (0xFF1B, 0xFF), (0xFF1C, 0x9F), (0xFF1D, 0xFF), (0xFF1E, 0xBF),
+            (0xFF20, 0xFF), (0xFF21, 0x00), (0xFF22, 0x00), (0xFF23, 0xBF),
+            (0xFF24, 0x77), (0xFF25, 0xF3), (0xFF26, post.nr52),
+        ];
+        for (addr, val) in apu {
+            bus.apu.write(addr, val);
+        }
+
+        let ppu = &mut bus.ppu;
+        ppu.lcdc = 0x91;
+        ppu.write_stat(0x85);
+        ppu.bgp = 0xFC;
+        ppu.obp0 = 0xFF;
+        ppu.obp1 = 0xFF;
+        if post.logo {
+            boot::draw_logo(&mut ppu.vram, &bus.cart.rom_image[0x104..0x134]);
+        }
     }
 
     pub fn tick(&mut self) -> ControlFlow<()> {
@@ -41,13 +319,21 @@ impl GB {
         }
 
         self.cpu.tick(&mut self.bus);
+        let brk = self.cpu.take_brk();
+        // STOP halts the system counter and the PPU along with the CPU
+        let stopped = self.cpu.stopped();
 
-        if self.bus.ppu.tick() {
-            self.bus.intr.raise(IntrSrc::VBlank);
+        self.bus.tick_dma();
+        if !stopped {
+            self.bus.ppu.tick(&mut self.bus.intr);
         }
 
         self.bus.apu.tick(0);
-        self.bus.timer.tick(&mut self.bus.intr);
+        if !stopped {
+            self.bus.timer.tick(&mut self.bus.intr);
+        }
+        self.bus.serial.tick(&mut self.bus.intr);
+        self.bus.cart.tick();
 
         self.bus.intr.tick(&mut self.cpu);
 
@@ -58,7 +344,11 @@ impl GB {
         // }
 
         self.tick += 1;
-        ControlFlow::Continue(())
+        if brk {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
     }
 
     pub fn stack_dump(&self) {
@@ -71,3 +361,28 @@ impl GB {
         }
     }
 }
+
+impl State for GB {
+    fn save(&self, w: &mut Writer) {
+        state::save_header(w, &self.bus.cart.rom_id());
+        w.u8(self.model as u8);
+        w.bytes(&self.tick.to_le_bytes());
+        self.cpu.save(w);
+        self.bus.save(w);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        state::load_header(r, &self.bus.cart.rom_id())?;
+        self.model = match r.u8()? {
+            0 => Model::Dmg0,
+            1 => Model::Dmg,
+            2 => Model::Mgb,
+            3 => Model::Sgb,
+            4 => Model::Cgb,
+            _ => return Err(StateError::Invalid),
+        };
+        self.tick = u128::from_le_bytes(r.take(16)?.try_into().unwrap());
+        self.cpu.load(r)?;
+        self.bus.load(r)
+    }
+}
@@ -1,18 +1,27 @@
-use core::slice;
-
 use crate::bus;
 use crate::gb;
+use crate::intr::{Intr, IntrSrc};
+use crate::state::{Reader, State, StateError, Writer};
 
 use crate::*;
 
+// an object OAM scan found on the line, its tile row fetched in mode 3
 struct ObjLine {
-    ci: [u8; 8],
     x: u8,
-    palette: bool,
+    addr: u16, // of the tile row in VRAM
+    attr: u8,
+    fetched: bool,
+}
+
+#[derive(Clone, Copy, Default)]
+struct ObjPixel {
+    ci: u8,
+    palette: bool,   // OBP1 rather than OBP0
+    behind_bg: bool, // BG colors 1-3 are drawn over it
 }
 
 #[derive(Clone, Copy)]
-#[repr(packed)]
+#[repr(C, packed)]
 struct Obj {
     y: u8,
     x: u8,
@@ -29,29 +38,63 @@ impl Obj {
     const CGB_PALETTE: u8 = 0x07;
 }
 
+// how mode 3 is drawn: dot by dot through the pixel FIFOs, with every
+// mid-line register write landing where it does on hardware, or a whole
+// line at once as mode 3 ends, which runs the emulator about twice as fast
+// and is the same on games that leave the registers alone while a line is
+// drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Renderer {
+    #[default]
+    Fifo,
+    Scanline,
+}
+
 pub struct Ppu {
     pub(crate) frame_buffer: [u8; gb::FRAME_BUFFER_SIZE],
     pub(crate) tile_image: [u8; 0x40000],
 
+    pub(crate) renderer: Renderer, // a host setting, kept out of states
     hdot: u16, // logical dot (progress) in one hline
+    stat_line: bool, // OR of every selected STAT interrupt condition
+    first_frame: bool, // since the LCD was turned on, not shown
 
     // OBJ rendering states
     objs: [ObjLine; 10],
     obj_put: u8,
     obj_fetch: u8,
+    obj_dots: u8, // into fetching the tile row of the next object
 
     // internal Mode 3 states
-    draw: bool,   // if we are in Mode 3
     lx: u8,       // physical dot on screen
-    penalty: u8,  // counter for penalty simulation
     sc3_line: u8, // low 3 bits of scx for this line
-
-    // tile-specific state
-    tile_line: [u8; 8],
-    tile_obj_drawn: bool,
+    discard: u8,  // pixels still to drop off the front of the line
+    line_end: u16, // hdot the scanline renderer ends mode 3 at
+
+    // pixel FIFOs, next pixel out first
+    bg_fifo: [u8; 8], // BG and window color indices, the last bg_len of them
+    bg_len: u8,
+    obj_fifo: [ObjPixel; 8],
+
+    // BG and window fetcher
+    fetch_dot: u8,      // into the current tile, which is ready at 6
+    fetch_dummy: bool,  // the first tile of a line is fetched twice
+    fetch_col: u8,      // tiles pushed this line
+    fetch_idx: u8,      // tile number
+    fetch_data: [u8; 2],
+
+    // window states
+    wy_latch: bool,     // LY matched WY this frame
+    wn_line: u8,        // window row, counting only lines it was drawn on
+    wn_active: bool,    // drawing the window from wn_start on
+    wn_drawn: bool,     // the window was drawn on this line
+    wn_next_line: bool, // WX=166 hit, the next line is all window
+    wn_start: u8,       // lx the window last started at
+    wn_col: u8,         // window tile of the next fetch
 
     // memory/registers
-    vram: [u8; 0x2000], // 8000..9FFF
+    pub(crate) vram: [u8; 0x2000], // 8000..9FFF
     oam: [Obj; 40],     // FE00..FE9F
 
     pub(crate) lcdc: u8,
@@ -77,45 +120,179 @@ impl Ppu {
     const LCDC_OBJ_ENABLE: u8 = 0x02;
     const LCDC_BGWN_PRIO: u8 = 0x01;
 
-    pub const fn init(&mut self) {}
+    const STAT_LYC_SELECT: u8 = 0x40;
+    const STAT_MODE2_SELECT: u8 = 0x20;
+    const STAT_MODE1_SELECT: u8 = 0x10;
+    const STAT_MODE0_SELECT: u8 = 0x08;
+    const STAT_LYC: u8 = 0x04;
+
+    pub const fn init(&mut self) {
+        // the LCD starts out off
+        let mut i = 0;
+        while i < self.frame_buffer.len() {
+            self.frame_buffer[i] = 0xFF;
+            i += 1;
+        }
+    }
+
+    // the screen as it shows with nothing driving it
+    pub fn blank(&mut self) {
+        self.frame_buffer.fill(0xFF);
+    }
+
+    pub fn lcd_on(&self) -> bool {
+        self.lcdc & Ppu::LCDC_ENABLE != 0
+    }
+
+    // turning the LCD off parks the PPU at the start of line 0 with a blank
+    // screen; turning it back on starts from there on a line 0 that comes
+    // up 4 dots short and skips OAM scan, and the frame isn't shown
+    // see https://gbdev.io/pandocs/LCDC.html#lcdc7--lcd-and-ppu-enable
+    pub fn write_lcdc(&mut self, val: u8) {
+        let was_on = self.lcd_on();
+        self.lcdc = val;
+        if was_on && !self.lcd_on() {
+            self.ly = 0;
+            self.hdot = 0;
+            self.lx = 0;
+            self.obj_put = 0;
+            self.obj_fetch = 0;
+            self.stat_line = false;
+            self.wy_latch = false;
+            self.wn_line = 0;
+            self.wn_active = false;
+            self.wn_drawn = false;
+            self.wn_next_line = false;
+            self.blank();
+        } else if !was_on && self.lcd_on() {
+            self.hdot = 3;
+            self.first_frame = true;
+        }
+    }
+
+    // see https://gbdev.io/pandocs/STAT.html
+    fn mode(&self) -> u8 {
+        if !self.lcd_on() || self.first_frame && self.ly == 0 && self.hdot < 80 {
+            0
+        } else if self.ly >= gb::FRAME_HEIGHT as u8 {
+            1
+        } else if self.hdot < 80 {
+            2
+        } else if self.lx < gb::FRAME_WIDTH as u8 {
+            3
+        } else {
+            0
+        }
+    }
+
+    // only the interrupt selects are writable
+    pub fn read_stat(&self) -> u8 {
+        let lyc = if self.ly == self.lyc { Ppu::STAT_LYC } else { 0 };
+        0x80 | self.stat | lyc | self.mode()
+    }
+
+    pub fn write_stat(&mut self, val: u8) {
+        self.stat = val & 0x78;
+    }
+
+    // the interrupt fires on a rising edge of the OR of all selected
+    // conditions, so while one holds the line high the others are blocked
+    fn update_stat(&mut self, intr: &mut Intr) {
+        let mode = self.mode();
+        let line = (self.stat & Ppu::STAT_LYC_SELECT != 0 && self.ly == self.lyc)
+            || (self.stat & Ppu::STAT_MODE0_SELECT != 0 && mode == 0)
+            || (self.stat & Ppu::STAT_MODE1_SELECT != 0 && mode == 1)
+            // the mode 2 select also catches the first dot of VBlank
+            || (self.stat & Ppu::STAT_MODE2_SELECT != 0
+                && (mode == 2 || self.ly == gb::FRAME_HEIGHT as u8 && self.hdot == 0));
+        if line && !self.stat_line {
+            intr.raise(IntrSrc::Lcd);
+        }
+        self.stat_line = line;
+    }
+
+    // the PPU has OAM to itself during OAM scan and drawing, and VRAM
+    // during drawing; the CPU reads 0xFF and its writes go nowhere
+    // see https://gbdev.io/pandocs/Accessing_VRAM_and_OAM.html
+    fn vram_locked(&self) -> bool {
+        self.mode() == 3
+    }
+
+    fn oam_locked(&self) -> bool {
+        matches!(self.mode(), 2 | 3)
+    }
 
-    // TODO(yhr0x43): memory locking
     pub fn read_vram(&self, addr: bus::Addr) -> u8 {
+        if self.vram_locked() {
+            return 0xFF;
+        }
         self.vram[(addr as usize) - 0x8000]
     }
 
     pub fn write_vram(&mut self, addr: bus::Addr, val: u8) {
-        self.vram[(addr as usize) - 0x8000] = val
+        if !self.vram_locked() {
+            self.vram[(addr as usize) - 0x8000] = val
+        }
     }
 
     pub fn read_oam(&self, addr: bus::Addr) -> u8 {
-        (unsafe { slice::from_raw_parts(self.oam.as_ptr() as *const u8, 160) })
-            [(addr as usize) - 0xFE00]
+        if self.oam_locked() {
+            return 0xFF;
+        }
+        self.oam()[(addr as usize) - 0xFE00]
     }
 
     pub fn write_oam(&mut self, addr: bus::Addr, val: u8) {
-        (unsafe { slice::from_raw_parts_mut(self.oam.as_mut_ptr() as *mut u8, 160) })
-            [(addr as usize) - 0xFE00] = val
+        if !self.oam_locked() {
+            self.oam_mut()[(addr as usize) - 0xFE00] = val
+        }
     }
 
-    pub fn tick(&mut self) -> bool {
-        if self.lcdc & Ppu::LCDC_ENABLE == 0 {
-            return false;
+    // OAM as the 160 bytes at FE00..FE9F
+    pub fn oam(&self) -> &[u8; 0xA0] {
+        unsafe { &*(self.oam.as_ptr() as *const [u8; 0xA0]) }
+    }
+
+    pub fn oam_mut(&mut self) -> &mut [u8; 0xA0] {
+        unsafe { &mut *(self.oam.as_mut_ptr() as *mut [u8; 0xA0]) }
+    }
+
+    pub fn tick(&mut self, intr: &mut Intr) {
+        // nothing runs with the LCD off, VBlank included
+        if !self.lcd_on() {
+            return;
         }
 
-        if self.dot() {
-            return true;
-        };
-        if self.dot() {
-            return true;
-        };
-        if self.dot() {
-            return true;
-        };
-        if self.dot() {
+        // the mode and LY hold, so one STAT update does for all 4 dots
+        if self.renderer == Renderer::Scanline && self.idle_cycle() {
+            self.hdot += 4;
+            self.update_stat(intr);
+            return;
+        }
+
+        for _ in 0..4 {
+            if self.dot() {
+                intr.raise(IntrSrc::VBlank);
+            }
+            self.update_stat(intr);
+        }
+    }
+
+    // whether the next 4 dots pass without a new line, an OAM scan step,
+    // the start of mode 3 or the scanline renderer drawing the line
+    fn idle_cycle(&self) -> bool {
+        let end = self.hdot + 4;
+        if end > 455 {
+            return false;
+        }
+        if self.ly >= gb::FRAME_HEIGHT as u8 || self.lx >= gb::FRAME_WIDTH as u8 {
             return true;
-        };
-        false
+        }
+        if self.hdot < 80 {
+            end < 80 && (self.lcdc & Ppu::LCDC_OBJ_ENABLE == 0 || end / 8 == self.hdot / 8)
+        } else {
+            end + 1 < self.line_end
+        }
     }
 
     fn decode_2bpp(val: &[u8; 2], flip: bool) -> [u8; 8] {
@@ -139,13 +316,7 @@ impl Ppu {
         let tile_y = (map_y as usize) / 8;
 
         let tile_idx = self.vram[map_base + tile_x + tile_y * 0x20];
-
-        let tile_addr = if self.lcdc & Ppu::LCDC_TILE_DATA == 0 {
-            (0x1000 + (tile_idx.cast_signed() as i16) * 0x10).cast_unsigned()
-        } else {
-            0x0000 + (tile_idx as u16) * 0x10
-        } as usize
-            + ((map_y % 8) * 2) as usize;
+        let tile_addr = self.tile_addr(tile_idx, map_y % 8);
 
         Ppu::decode_2bpp(
             self.vram[tile_addr..(tile_addr + 2)].try_into().unwrap(),
@@ -165,11 +336,26 @@ impl Ppu {
             if self.ly > 153 {
                 self.ly = 0;
             }
+
+            // the window line only advances on lines the window was drawn
+            if self.wn_drawn {
+                self.wn_line = self.wn_line.wrapping_add(1);
+            }
+            self.wn_active = self.wn_next_line;
+            self.wn_drawn = self.wn_next_line;
+            self.wn_next_line = false;
+            self.wn_start = 0;
+            self.wn_col = 0;
         }
 
         // VBlank
         if self.ly >= gb::FRAME_HEIGHT as u8 {
             if self.ly == gb::FRAME_HEIGHT as u8 && self.hdot == 0 {
+                self.first_frame = false;
+                self.wy_latch = false;
+                self.wn_line = 0;
+                self.wn_active = false;
+                self.wn_drawn = false;
                 return true;
             }
             return false;
@@ -186,42 +372,9 @@ impl Ppu {
         }
 
         if self.hdot < 80 {
-            if self.lcdc & Ppu::LCDC_OBJ_ENABLE != 0 && self.hdot.is_multiple_of(8) {
-                while self.obj_fetch < 40 {
-                    let this_obj = &self.oam[self.obj_fetch as usize];
-                    let obj_tall = self.lcdc & Ppu::LCDC_OBJ_SIZE != 0;
-                    let mode_dy = if obj_tall { 16 } else { 8 };
-
-                    if self.ly.wrapping_sub(this_obj.y.wrapping_sub(16)) < mode_dy {
-                        let tile_y = if this_obj.attr & Obj::YFLIP != 0 {
-                            mode_dy - self.ly % mode_dy
-                        } else {
-                            self.ly % mode_dy
-                        };
-                        let tile_addr = if obj_tall {
-                            this_obj.tile & 0xFE
-                        } else {
-                            this_obj.tile
-                        } as usize
-                            * 0x10
-                            + 2 * tile_y as usize;
-
-                        let tiles = &self.vram[tile_addr..tile_addr + 2];
-
-                        self.objs[self.obj_put as usize] = ObjLine {
-                            ci: Ppu::decode_2bpp(
-                                tiles.try_into().unwrap(),
-                                this_obj.attr & Obj::XFLIP != 0,
-                            ),
-                            x: this_obj.x,
-                            palette: this_obj.attr & Obj::DMG_PALETTE != 0,
-                        };
-                        self.obj_put += 1;
-                        self.obj_fetch += 1;
-                        return false;
-                    }
-                    self.obj_fetch += 1;
-                }
+            // there is no OAM scan on the line the LCD comes on
+            if self.lcdc & Ppu::LCDC_OBJ_ENABLE != 0 && self.hdot.is_multiple_of(8) && self.mode() == 2 {
+                self.scan_obj();
             }
             return false;
         }
@@ -229,97 +382,396 @@ impl Ppu {
         // begin Mode 3
         if self.hdot == 80 {
             self.sc3_line = self.scx % 8;
-            self.penalty = self.sc3_line;
+            // the window from WX=166 owns the whole line, scroll and all
+            self.discard = if self.wn_active { 0 } else { self.sc3_line };
+            self.bg_len = 0;
+            self.obj_fifo = [ObjPixel::default(); 8];
+            self.obj_dots = 0;
+            self.fetch_dot = 0;
+            self.fetch_dummy = true;
+            self.fetch_col = 0;
+            // once LY matched WY the window stays armed for the frame
+            self.wy_latch |= self.ly == self.wy;
+            self.line_end = 80 + self.mode3_len();
         }
 
-        if self.penalty > 0 {
-            self.penalty -= 1;
+        if self.renderer == Renderer::Scanline {
+            if self.hdot + 1 == self.line_end {
+                self.render_line();
+            }
             return false;
         }
 
-        let obj_color = if self.lcdc & Ppu::LCDC_OBJ_ENABLE != 0 {
-            // TODO object occlusion
-            let mut color = 0x00;
-            for obj in &self.objs[..self.obj_put as usize] {
-                let dx = self.lx.wrapping_sub(obj.x.wrapping_sub(8));
-                if dx < 8 {
-                    self.tile_obj_drawn = true;
-                    if obj.ci[dx as usize] != 0x00 {
-                        let pal = if obj.palette { self.obp0 } else { self.obp1 };
-                        let ci = obj.ci[dx as usize];
-                        color = (pal & (0x3 << (ci * 2))) >> ci * 2;
-                        break;
-                    }
-                }
+        self.window_trigger();
+        // the FIFO stalls while an object is fetched
+        if self.obj_step() {
+            return false;
+        }
+        if self.bg_len > 0 {
+            self.shift_pixel();
+        }
+        self.fetch();
+        // end Mode 3
+        false
+    }
+
+    // finds the next object on the line in OAM; run every 8 dots of OAM
+    // scan, which makes for at most 10
+    fn scan_obj(&mut self) {
+        while self.obj_fetch < 40 {
+            let this_obj = self.oam[self.obj_fetch as usize];
+            let obj_tall = self.lcdc & Ppu::LCDC_OBJ_SIZE != 0;
+            let mode_dy = if obj_tall { 16 } else { 8 };
+            self.obj_fetch += 1;
+
+            let row = self.ly.wrapping_sub(this_obj.y.wrapping_sub(16));
+            if row < mode_dy {
+                let tile_y = if this_obj.attr & Obj::YFLIP != 0 {
+                    mode_dy - 1 - row
+                } else {
+                    row
+                };
+                // the bottom half of a tall object is the next tile
+                let tile = if obj_tall { this_obj.tile & 0xFE } else { this_obj.tile };
+
+                self.objs[self.obj_put as usize] = ObjLine {
+                    x: this_obj.x,
+                    addr: tile as u16 * 0x10 + 2 * tile_y as u16,
+                    attr: this_obj.attr,
+                    fetched: false,
+                };
+                self.obj_put += 1;
+                return;
             }
-            color
+        }
+    }
+
+    // one step of the BG fetcher: tile number, low and high byte of its
+    // row at 2 dots each, then waiting for the FIFO to run empty to push
+    // the 8 pixels; the window takes over when it is active
+    fn fetch(&mut self) {
+        if self.fetch_dot < 6 {
+            self.fetch_dot += 1;
+            let (map_base, col, y) = if self.wn_active {
+                (if self.lcdc & Ppu::LCDC_WN_MAP == 0 { 0x1800 } else { 0x1C00 }, self.wn_col, self.wn_line)
+            } else {
+                let map_base = if self.lcdc & Ppu::LCDC_BG_MAP == 0 { 0x1800 } else { 0x1C00 };
+                (map_base, (self.scx >> 3).wrapping_add(self.fetch_col), self.ly.wrapping_add(self.scy))
+            };
+            match self.fetch_dot {
+                2 => self.fetch_idx = self.vram[map_base + (col % 32) as usize + (y / 8) as usize * 0x20],
+                4 => self.fetch_data[0] = self.vram[self.tile_addr(self.fetch_idx, y % 8)],
+                6 => self.fetch_data[1] = self.vram[self.tile_addr(self.fetch_idx, y % 8) + 1],
+                _ => {}
+            }
+        }
+
+        if self.fetch_dot == 6 && self.fetch_dummy {
+            self.fetch_dummy = false;
+            self.fetch_dot = 0;
+        } else if self.fetch_dot == 6 && self.bg_len == 0 {
+            self.bg_fifo = Ppu::decode_2bpp(&self.fetch_data, false);
+            self.bg_len = 8;
+            self.fetch_dot = 0;
+            self.fetch_col = self.fetch_col.wrapping_add(1);
+            if self.wn_active {
+                self.wn_col = self.wn_col.wrapping_add(1);
+            }
+        }
+    }
+
+    // address of a row of BG or window tile data in VRAM
+    fn tile_addr(&self, tile: u8, row: u8) -> usize {
+        let base = if self.lcdc & Ppu::LCDC_TILE_DATA == 0 {
+            (0x1000 + (tile.cast_signed() as i16) * 0x10).cast_unsigned()
         } else {
-            0x00
+            (tile as u16) * 0x10
         };
+        base as usize + row as usize * 2
+    }
 
-        let bg_color = if self.lcdc & Ppu::LCDC_BGWN_PRIO != 0 {
-            let window_color = if self.lcdc & Ppu::LCDC_WN_ENABLE != 0 {
-                let wm_map_x = self.lx.wrapping_sub(self.wx);
-                let wm_map_y = self.ly.wrapping_sub(self.wy);
-
-                if self.lx < self.wx || self.ly < self.wy {
-                    if self.lx + 1 == self.wx {
-                        self.penalty = 6;
-                        return false;
-                    };
-                    0x00
-                } else if wm_map_x < 160 && wm_map_y < 144 {
-                    let map_base = if self.lcdc & Ppu::LCDC_WN_MAP == 0 {
-                        0x1800
-                    } else {
-                        0x1C00
-                    };
+    // fetches the object starting at lx, the lowest X first; true while it
+    // keeps the FIFO stalled
+    fn obj_step(&mut self) -> bool {
+        if self.lcdc & Ppu::LCDC_OBJ_ENABLE == 0 {
+            self.obj_dots = 0;
+            return false;
+        }
+        let lx = self.lx;
+        let next = self.objs[..self.obj_put as usize]
+            .iter()
+            .enumerate()
+            .filter(|(_, obj)| !obj.fetched && (obj.x == lx + 8 || lx == 0 && obj.x < 8))
+            .min_by_key(|(_, obj)| obj.x)
+            .map(|(i, _)| i);
+        let Some(i) = next else {
+            return false;
+        };
 
-                    let tile_line = self
-                        .fetch_tile(map_base, wm_map_x, wm_map_y)
-                        .map(|ci| (self.bgp & 0x3 << ci * 2) >> ci * 2);
-                    tile_line[(wm_map_x % 8) as usize]
-                } else {
-                    0x00
-                }
-            } else {
-                0x00
+        // the BG fetcher gets its tile ready first, then the object takes
+        // 6 dots
+        if self.obj_dots == 0 && self.fetch_dot != 6 && self.bg_len != 8 {
+            self.fetch();
+            return true;
+        }
+        self.obj_dots += 1;
+        if self.obj_dots < 6 {
+            return true;
+        }
+        self.obj_dots = 0;
+
+        let obj = &mut self.objs[i];
+        obj.fetched = true;
+        let addr = obj.addr as usize;
+        let ci = Ppu::decode_2bpp(&[self.vram[addr], self.vram[addr + 1]], obj.attr & Obj::XFLIP != 0);
+        let pixel = ObjPixel {
+            ci: 0,
+            palette: obj.attr & Obj::DMG_PALETTE != 0,
+            behind_bg: obj.attr & Obj::PRIORITY != 0,
+        };
+        // on DMG an object only fills in where the ones before it, with a
+        // lower X or earlier in OAM, are transparent
+        for (p, ci) in ci.into_iter().enumerate() {
+            let Some(slot) = (obj.x as usize + p).checked_sub(lx as usize + 8) else {
+                continue;
             };
+            if ci != 0 && self.obj_fifo[slot].ci == 0 {
+                self.obj_fifo[slot] = ObjPixel { ci, ..pixel };
+            }
+        }
+        true
+    }
 
-            if window_color != 0x00 {
-                window_color
-            } else {
-                let map_x = self.lx.wrapping_add(self.scx & 0xF8 | self.sc3_line);
-                let map_y = self.ly.wrapping_add(self.scy);
-
-                if self.lx == 0 || map_x.is_multiple_of(8) {
-                    let map_base = if self.lcdc & Ppu::LCDC_BG_MAP == 0 {
-                        0x1800
-                    } else {
-                        0x1C00
-                    };
+    // mixes the pixels at the front of both FIFOs onto the screen at lx
+    fn shift_pixel(&mut self) {
+        let bg_ci = self.bg_fifo[(8 - self.bg_len) as usize];
+        self.bg_len -= 1;
+        if self.discard > 0 {
+            self.discard -= 1;
+            return;
+        }
+        let obj = self.obj_fifo[0];
+        self.obj_fifo.copy_within(1.., 0);
+        self.obj_fifo[7] = ObjPixel::default();
+
+        self.put_pixel(self.lx, bg_ci, obj);
+        self.lx += 1;
+    }
+
+    fn put_pixel(&mut self, x: u8, bg_ci: u8, obj: ObjPixel) {
+        // with BG and window off only objects are drawn, over color 0
+        let bg_ci = if self.lcdc & Ppu::LCDC_BGWN_PRIO != 0 { bg_ci } else { 0x00 };
+        let obj_shown = self.lcdc & Ppu::LCDC_OBJ_ENABLE != 0 && obj.ci != 0;
+
+        let color = match obj {
+            // BG-over-OBJ only lets BG colors 1-3 through
+            ObjPixel { ci, palette, behind_bg } if obj_shown && !(behind_bg && bg_ci != 0) => {
+                Ppu::palette(if palette { self.obp1 } else { self.obp0 }, ci)
+            }
+            _ if self.lcdc & Ppu::LCDC_BGWN_PRIO != 0 => Ppu::palette(self.bgp, bg_ci),
+            _ => 0,
+        };
+        let tgt = (x as usize + self.ly as usize * gb::FRAME_WIDTH) * 4;
+        if !self.first_frame {
+            self.frame_buffer[tgt..tgt + 4].copy_from_slice(&Ppu::map_color(color));
+        }
+    }
 
-                    self.tile_line = self
-                        .fetch_tile(map_base, map_x, map_y)
-                        .map(|ci| (self.bgp & 0x3 << ci * 2) >> ci * 2);
+    // the line as the FIFOs would draw it if no register changed during
+    // mode 3, with the objects found by OAM scan and the window where WX
+    // starts it
+    fn render_line(&mut self) {
+        let mut obj_line = [ObjPixel::default(); gb::FRAME_WIDTH];
+        if self.lcdc & Ppu::LCDC_OBJ_ENABLE != 0 {
+            // lowest X first, then OAM order, each filling in what the ones
+            // before left transparent
+            let mut order = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+            let order = &mut order[..self.obj_put as usize];
+            order.sort_unstable_by_key(|&i| (self.objs[i].x, i));
+            for &i in order.iter() {
+                let obj = &self.objs[i];
+                let addr = obj.addr as usize;
+                let ci = Ppu::decode_2bpp(&[self.vram[addr], self.vram[addr + 1]], obj.attr & Obj::XFLIP != 0);
+                for (p, ci) in ci.into_iter().enumerate() {
+                    let Some(x) = (obj.x as usize + p).checked_sub(8) else {
+                        continue;
+                    };
+                    if x < gb::FRAME_WIDTH && ci != 0 && obj_line[x].ci == 0 {
+                        obj_line[x] = ObjPixel {
+                            ci,
+                            palette: obj.attr & Obj::DMG_PALETTE != 0,
+                            behind_bg: obj.attr & Obj::PRIORITY != 0,
+                        };
+                    }
                 }
+            }
+        }
+
+        let window = self.line_window();
+        let wn_enable = self.lcdc & Ppu::LCDC_WN_ENABLE != 0;
+        self.wn_drawn |= window.is_some();
+        self.wn_next_line = wn_enable && self.wy_latch && self.wx == 166;
 
-                self.tile_line[map_x as usize % 8]
+        self.lx = gb::FRAME_WIDTH as u8;
+        if self.first_frame {
+            return;
+        }
+
+        // a whole tile at a time, the first and last cut short by the scroll
+        // and the start of the window
+        let mut bg_line = [0; gb::FRAME_WIDTH];
+        let (bg_end, wn) = match window {
+            Some((start, col)) => (start as usize, Some(col)),
+            None => (gb::FRAME_WIDTH, None),
+        };
+        let bg_map = if self.lcdc & Ppu::LCDC_BG_MAP == 0 { 0x1800 } else { 0x1C00 };
+        let map_y = self.ly.wrapping_add(self.scy);
+        let mut x = 0;
+        while x < bg_end {
+            let map_x = (x as u8).wrapping_add(self.scx);
+            let tile = self.fetch_tile(bg_map, map_x, map_y);
+            let skip = map_x as usize % 8;
+            let len = (8 - skip).min(bg_end - x);
+            bg_line[x..x + len].copy_from_slice(&tile[skip..skip + len]);
+            x += len;
+        }
+        if let Some(col) = wn {
+            let wn_map = if self.lcdc & Ppu::LCDC_WN_MAP == 0 { 0x1800 } else { 0x1C00 };
+            while x < gb::FRAME_WIDTH {
+                let wn_x = col + (x - bg_end) as u8;
+                let tile = self.fetch_tile(wn_map, wn_x, self.wn_line);
+                let skip = wn_x as usize % 8;
+                let len = (8 - skip).min(gb::FRAME_WIDTH - x);
+                bg_line[x..x + len].copy_from_slice(&tile[skip..skip + len]);
+                x += len;
             }
+        }
+
+        for (x, (&bg_ci, &obj)) in bg_line.iter().zip(&obj_line).enumerate() {
+            self.put_pixel(x as u8, bg_ci, obj);
+        }
+    }
+
+    // first screen column of the window and the window column under it,
+    // if the window shows on this line
+    fn line_window(&self) -> Option<(u8, u8)> {
+        if self.lcdc & Ppu::LCDC_WN_ENABLE == 0 {
+            None
+        } else if self.wn_active {
+            Some((0, 0))
+        } else if !self.wy_latch {
+            None
         } else {
-            0x00
+            match self.wx {
+                0 => Some((0, 7 + self.sc3_line)),
+                wx @ 1..7 => Some((0, 7 - wx)),
+                wx @ 7..166 => Some((wx - 7, 0)),
+                _ => None,
+            }
+        }
+    }
+
+    // dots the FIFOs take over mode 3 when no register changes during it,
+    // for the scanline renderer to end it at the same dot: 12 to fetch the
+    // first tile twice, one per pixel, the ones dropped off the front
+    // included, 6 to start the window part way along, and 6 per object,
+    // after waiting for the BG fetcher to get its tile ready; see obj_step
+    // and window_trigger
+    fn mode3_len(&self) -> u16 {
+        // the window from the start of the line drops its columns up to
+        // the one under x 0 instead of the fine scroll
+        let (wn_start, discard) = match self.line_window() {
+            Some((0, col)) => (None, col),
+            Some((start, _)) => (Some(start), self.discard),
+            None => (None, self.discard),
         };
+        let mut len = 12 + gb::FRAME_WIDTH as u16 + discard as u16;
+        if wn_start.is_some() {
+            len += 6;
+        }
+        if self.lcdc & Ppu::LCDC_OBJ_ENABLE == 0 {
+            return len;
+        }
 
-        let tgt = (self.lx as usize + self.ly as usize * gb::FRAME_WIDTH) * 4;
-        if obj_color != 0 {
-            self.frame_buffer[tgt..tgt + 4].copy_from_slice(&Ppu::map_color(obj_color));
-        } else {
-            self.frame_buffer[tgt..tgt + 4].copy_from_slice(&Ppu::map_color(bg_color));
+        let mut xs = [0; 10];
+        let xs = &mut xs[..self.obj_put as usize];
+        for (x, obj) in xs.iter_mut().zip(&self.objs) {
+            *x = obj.x;
         }
+        xs.sort_unstable();
+        // the tile the BG fetcher last got ready early, which it then
+        // holds on to until the FIFO runs empty
+        let mut ready = None;
+        for &x in xs.iter() {
+            // past the last pixel
+            if x >= gb::FRAME_WIDTH as u8 + 8 {
+                break;
+            }
+            len += 6;
+            // those at the start of the line wait out the first tile
+            // anyway
+            let Some(lx) = x.checked_sub(8).filter(|&lx| lx > 0) else {
+                continue;
+            };
+            // pixels from the start of the BG or of the window, which
+            // are fetched from there in whole tiles
+            let pixel = match wn_start {
+                Some(start) if lx >= start => 0x100 + (lx - start) as u16,
+                _ => lx as u16 + discard as u16,
+            };
+            // the fetcher is on dot pixel % 8 - 1 of 6 of the next tile
+            if ready != Some(pixel / 8) && (1..6).contains(&(pixel % 8)) {
+                len += 6 - pixel % 8;
+                ready = Some(pixel / 8);
+            }
+        }
+        len
+    }
 
-        self.lx += 1;
-        // end Mode 3
-        false
+    // starts the window over at lx if WX says so, which empties the FIFO
+    // for the fetcher to start on the window
+    // see https://gbdev.io/pandocs/Window.html
+    fn window_trigger(&mut self) {
+        if self.lcdc & Ppu::LCDC_WN_ENABLE == 0 {
+            // the BG carries on from where it is, until WX matches again
+            self.wn_active = false;
+            return;
+        }
+        if !self.wy_latch {
+            return;
+        }
+
+        // WX=166 would start the window past the last pixel, which on DMG
+        // makes it take up the whole of the next line instead
+        if self.wx == 166 {
+            self.wn_next_line |= self.lx + 1 == gb::FRAME_WIDTH as u8;
+            return;
+        }
+        // WX below 7 starts it part way in, and WX=0 also loses the BG fine
+        // scroll to it
+        let discard = match self.wx {
+            0 if self.lx == 0 => 7 + self.sc3_line,
+            wx @ 1..7 if self.lx == 0 => 7 - wx,
+            // a WX moved further right retriggers it on the same line
+            wx if self.lx + 7 == wx => 0,
+            _ => return,
+        };
+        if self.wn_active && self.wn_start == self.lx {
+            return;
+        }
+
+        self.wn_active = true;
+        self.wn_drawn = true;
+        self.wn_start = self.lx;
+        self.wn_col = 0;
+        self.discard = discard;
+        self.bg_len = 0;
+        self.fetch_dot = 0;
+    }
+
+    #[inline]
+    fn palette(pal: u8, ci: u8) -> u8 {
+        (pal >> (ci * 2)) & 0x3
     }
 
     #[inline]
@@ -356,3 +808,123 @@ impl Ppu {
         }
     }
 }
+
+impl State for Ppu {
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&self.frame_buffer);
+        w.u16(self.hdot);
+        w.bool(self.stat_line);
+        w.bool(self.first_frame);
+
+        for obj in &self.objs {
+            w.u8(obj.x);
+            w.u16(obj.addr);
+            w.u8(obj.attr);
+            w.bool(obj.fetched);
+        }
+        w.u8(self.obj_put);
+        w.u8(self.obj_fetch);
+        w.u8(self.obj_dots);
+
+        w.u8(self.lx);
+        w.u8(self.sc3_line);
+        w.u8(self.discard);
+        w.u16(self.line_end);
+
+        w.bytes(&self.bg_fifo);
+        w.u8(self.bg_len);
+        for pixel in &self.obj_fifo {
+            w.u8(pixel.ci);
+            w.bool(pixel.palette);
+            w.bool(pixel.behind_bg);
+        }
+
+        w.u8(self.fetch_dot);
+        w.bool(self.fetch_dummy);
+        w.u8(self.fetch_col);
+        w.u8(self.fetch_idx);
+        w.bytes(&self.fetch_data);
+
+        w.bool(self.wy_latch);
+        w.u8(self.wn_line);
+        w.bool(self.wn_active);
+        w.bool(self.wn_drawn);
+        w.bool(self.wn_next_line);
+        w.u8(self.wn_start);
+        w.u8(self.wn_col);
+
+        w.bytes(&self.vram);
+        w.bytes(self.oam());
+
+        w.bytes(&[self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc]);
+        w.bytes(&[self.bgp, self.obp0, self.obp1, self.wx, self.wy]);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        r.bytes(&mut self.frame_buffer)?;
+        self.hdot = r.u16()?;
+        self.stat_line = r.bool()?;
+        self.first_frame = r.bool()?;
+
+        for obj in &mut self.objs {
+            obj.x = r.u8()?;
+            obj.addr = r.u16()?;
+            obj.attr = r.u8()?;
+            obj.fetched = r.bool()?;
+        }
+        self.obj_put = r.u8()?;
+        self.obj_fetch = r.u8()?;
+        self.obj_dots = r.u8()?;
+
+        self.lx = r.u8()?;
+        self.sc3_line = r.u8()?;
+        self.discard = r.u8()?;
+        self.line_end = r.u16()?;
+
+        r.bytes(&mut self.bg_fifo)?;
+        self.bg_len = r.u8()?;
+        for pixel in &mut self.obj_fifo {
+            pixel.ci = r.u8()?;
+            pixel.palette = r.bool()?;
+            pixel.behind_bg = r.bool()?;
+        }
+
+        self.fetch_dot = r.u8()?;
+        self.fetch_dummy = r.bool()?;
+        self.fetch_col = r.u8()?;
+        self.fetch_idx = r.u8()?;
+        r.bytes(&mut self.fetch_data)?;
+
+        self.wy_latch = r.bool()?;
+        self.wn_line = r.u8()?;
+        self.wn_active = r.bool()?;
+        self.wn_drawn = r.bool()?;
+        self.wn_next_line = r.bool()?;
+        self.wn_start = r.u8()?;
+        self.wn_col = r.u8()?;
+
+        r.bytes(&mut self.vram)?;
+        r.bytes(self.oam_mut())?;
+
+        [self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc] = r.take(6)?.try_into().unwrap();
+        [self.bgp, self.obp0, self.obp1, self.wx, self.wy] = r.take(5)?.try_into().unwrap();
+        self.stat &= 0x78;
+
+        // out of range counters and colors would index past the frame
+        // buffer, objs, a FIFO, VRAM or a palette
+        let colors_ok = self.bg_fifo.iter().chain(self.obj_fifo.iter().map(|p| &p.ci)).all(|&c| c <= 3);
+        let objs_ok = self.objs.iter().all(|obj| obj.addr < 0x1000);
+        let counters_ok = self.hdot <= 455
+            && self.ly <= 153
+            && self.lx <= 160
+            && self.obj_put <= 10
+            && self.obj_fetch <= 40
+            && self.obj_dots < 6
+            && self.bg_len <= 8
+            && self.fetch_dot <= 6;
+        if !counters_ok || !colors_ok || !objs_ok {
+            return Err(StateError::Invalid);
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,9 @@
 use crate::cpu;
+use crate::state::{Reader, State, StateError, Writer};
 
 pub(crate) enum IntrSrc {
     VBlank = 0x01,
-    LCD = 0x02,
+    Lcd = 0x02,
     Timer = 0x04,
     Serial = 0x08,
     Joypad = 0x10,
@@ -20,27 +21,30 @@ impl Intr {
         self.reg_ie
     }
 
+    // all 8 bits are kept and read back, only the low 5 enable anything
     pub fn write_ie(&mut self, val: u8) {
-        self.reg_ie = val & 0x1F;
+        self.reg_ie = val;
     }
 
     pub fn read_if(&self) -> u8 {
-        self.reg_if
+        self.reg_if | 0xE0
     }
 
     pub fn write_if(&mut self, val: u8) {
         self.reg_if = val & 0x1F;
     }
 
+    // requested and enabled interrupts
+    pub fn pending(&self) -> u8 {
+        self.reg_if & self.reg_ie & 0x1F
+    }
+
     pub fn raise(&mut self, intr: IntrSrc) {
-        let intr = intr as u8;
-        if self.reg_ie & intr != 0 {
-            self.reg_if |= intr;
-        }
+        self.reg_if |= intr as u8;
     }
 
     pub fn tick(&mut self, cpu: &mut cpu::Cpu) {
-        let trail = (self.reg_if & self.reg_ie).trailing_zeros() as u16;
+        let trail = self.pending().trailing_zeros() as u16;
         if trail > 4 {
             return;
         }
@@ -49,3 +53,16 @@ impl Intr {
         }
     }
 }
+
+impl State for Intr {
+    fn save(&self, w: &mut Writer) {
+        w.u8(self.reg_ie);
+        w.u8(self.reg_if);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.reg_ie = r.u8()?;
+        self.reg_if = r.u8()? & 0x1F;
+        Ok(())
+    }
+}
@@ -1,35 +1,55 @@
 #![no_std]
 #![allow(unused)]
 
+extern crate alloc;
+
+// native builds link std for the allocator, panic handler and println!
+#[cfg(not(target_arch = "wasm32"))]
+#[macro_use]
+extern crate std;
+
 mod audio;
+mod bess;
+mod boot;
 mod bus;
 mod cart;
 mod cpu;
+mod dma;
 mod gb;
 mod graphic;
 mod intr;
+mod serial;
+mod state;
 mod timer;
 mod reg;
+mod rewind;
+mod rtc;
+#[cfg(target_arch = "wasm32")]
 #[macro_use]
 mod wasm;
 
-use core::alloc::{GlobalAlloc, Layout};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
 
+#[cfg(target_arch = "wasm32")]
 use crate::wasm::*;
 
+pub use crate::boot::Model;
+pub use crate::cart::{CartHeader, CgbSupport, LoadError};
+pub use crate::cpu::Registers;
+pub use crate::graphic::Renderer;
+pub use crate::rewind::Rewind;
+pub use crate::rtc::RTC_FOOTER_LEN;
+pub use crate::state::{STATE_MAX_SIZE, STATE_VERSION, StateError};
+pub use crate::gb::{CYCLES_PER_FRAME, FRAME_BUFFER_SIZE, FRAME_HEIGHT, FRAME_WIDTH, GB};
+
 #[unsafe(no_mangle)]
 pub fn gb_get() -> *mut gb::GB {
     // static mut GB_INSTANCE: gb::GB = gb::GB::new();
     // unsafe { &raw mut GB_INSTANCE }
     // println!("begin gb_get");
-    let layout = Layout::new::<gb::GB>();
-    unsafe {
-        let gb_ptr = ALLOCATOR.alloc_zeroed(layout) as *mut gb::GB;
-        if let Some(gb) = gb_ptr.as_mut() {
-            gb.init()
-        }
-        gb_ptr
-    }
+    Box::into_raw(gb::GB::new())
 }
 
 #[unsafe(no_mangle)]
@@ -42,9 +62,221 @@ pub fn get_bootrom_ptr(gb: &mut gb::GB) -> *const u8 {
     gb.bus.bootrom.as_ptr()
 }
 
+// to be called once the host has copied a boot ROM to get_bootrom_ptr,
+// resets so that it runs
+#[unsafe(no_mangle)]
+pub fn init_bootrom(gb: &mut gb::GB) {
+    let bootrom = gb.bus.bootrom;
+    gb.load_bootrom(&bootrom);
+}
+
+// a ROM on its way in, only held until init_gamerom inserts it
+#[cfg(target_arch = "wasm32")]
+static mut GAMEROM_BUF: Vec<u8> = Vec::new();
+
+// room for the host to copy a len byte ROM to
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub fn get_gamerom_ptr(len: usize) -> *mut u8 {
+    let buf = unsafe { &mut *(&raw mut GAMEROM_BUF) };
+    *buf = vec![0; len];
+    buf.as_mut_ptr()
+}
+
+// to be called once the host has copied the ROM to get_gamerom_ptr,
+// inserts it and resets; returns 0 on success, otherwise the code of the
+// LoadError that keeps it from running
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub fn init_gamerom(gb: &mut gb::GB) -> i32 {
+    let data = core::mem::take(unsafe { &mut *(&raw mut GAMEROM_BUF) });
+    match gb.load_rom(&data) {
+        Ok(()) => 0,
+        Err(e) => e.code(),
+    }
+}
+
+// title from the cart header, ASCII and not NUL terminated
+#[unsafe(no_mangle)]
+pub fn get_cart_title_ptr(gb: &gb::GB) -> *const u8 {
+    gb.header().title_bytes().as_ptr()
+}
+
+#[unsafe(no_mangle)]
+pub fn get_cart_title_len(gb: &gb::GB) -> usize {
+    gb.header().title_bytes().len()
+}
+
+#[unsafe(no_mangle)]
+pub fn get_cart_type(gb: &gb::GB) -> u8 {
+    gb.header().cart_type
+}
+
+#[unsafe(no_mangle)]
+pub fn get_cart_version(gb: &gb::GB) -> u8 {
+    gb.header().version
+}
+
+// as stored in the header, with the title enough to tell games apart
+#[unsafe(no_mangle)]
+pub fn get_cart_global_checksum(gb: &gb::GB) -> u16 {
+    gb.header().global_checksum
+}
+
+// battery RAM in the raw .sav layout, the host loads a save by writing
+// get_save_ram_len bytes here after init_gamerom
+#[unsafe(no_mangle)]
+pub fn get_save_ram_ptr(gb: &mut gb::GB) -> *mut u8 {
+    gb.bus.cart.save_ram_mut().as_mut_ptr()
+}
+
+#[unsafe(no_mangle)]
+pub fn get_save_ram_len(gb: &gb::GB) -> usize {
+    gb.save_ram().len()
+}
+
+// nonzero once the game wrote to save RAM since the last call
+#[unsafe(no_mangle)]
+pub fn take_save_ram_dirty(gb: &mut gb::GB) -> i32 {
+    gb.take_save_ram_dirty().into()
+}
+
+// the clock footer that follows the RAM in .sav files goes through this
+// buffer, see GB::save_rtc
+#[cfg(target_arch = "wasm32")]
+static mut RTC_BUF: [u8; RTC_FOOTER_LEN] = [0; RTC_FOOTER_LEN];
+
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub fn get_rtc_ptr() -> *mut u8 {
+    (&raw mut RTC_BUF).cast()
+}
+
+// stamped with now, the Unix time; returns the length of the footer, 0
+// for carts without a clock
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub fn save_rtc(gb: &gb::GB, now: u64) -> usize {
+    match gb.save_rtc(now) {
+        Some(footer) => {
+            unsafe { RTC_BUF = footer };
+            RTC_FOOTER_LEN
+        }
+        None => 0,
+    }
+}
+
+// to be called once the host has copied a footer to get_rtc_ptr, after
+// the RAM
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub fn load_rtc(gb: &mut gb::GB, now: u64) {
+    let footer = unsafe { RTC_BUF };
+    gb.load_rtc(&footer, now);
+}
+
+// save states go through this buffer, which gives the host a fixed place
+// to copy a state out of after save_state, or into before load_state
+#[cfg(target_arch = "wasm32")]
+static mut STATE_BUF: [u8; state::STATE_MAX_SIZE] = [0; state::STATE_MAX_SIZE];
+
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub fn get_state_ptr() -> *mut u8 {
+    (&raw mut STATE_BUF).cast()
+}
+
+// returns the length of the state, 0 on failure
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub fn save_state(gb: &gb::GB) -> usize {
+    let buf = unsafe { &mut *(&raw mut STATE_BUF) };
+    gb.save_state(buf).unwrap_or_else(|e| {
+        println!("failed to save state: {}", e);
+        0
+    })
+}
+
+// returns 0 on success, nonzero if the state doesn't fit this ROM
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub fn load_state(gb: &mut gb::GB, len: usize) -> i32 {
+    let buf = unsafe { &*(&raw const STATE_BUF) };
+    match gb.load_state(&buf[..len.min(buf.len())]) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("failed to load state: {}", e);
+            1
+        }
+    }
+}
+
+// BESS states for other emulators, through the same buffer; now is the
+// Unix time, for the cart clock
+#[cfg(target_arch = "wasm32")]
 #[unsafe(no_mangle)]
-pub fn get_gamerom_ptr(gb: &mut gb::GB) -> *const u8 {
-    gb.bus.cart.rom_image.as_ptr()
+pub fn save_bess(gb: &gb::GB, now: u64) -> usize {
+    let buf = unsafe { &mut *(&raw mut STATE_BUF) };
+    gb.save_bess(buf, now).unwrap_or_else(|e| {
+        println!("failed to save BESS state: {}", e);
+        0
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+#[unsafe(no_mangle)]
+pub fn load_bess(gb: &mut gb::GB, len: usize, now: u64) -> i32 {
+    let buf = unsafe { &*(&raw const STATE_BUF) };
+    match gb.load_bess(&buf[..len.min(buf.len())], now) {
+        Ok(()) => 0,
+        Err(e) => {
+            println!("failed to load BESS state: {}", e);
+            1
+        }
+    }
+}
+
+// polled by the host after each frame, nonzero while the motor runs
+#[unsafe(no_mangle)]
+pub fn get_rumble(gb: &gb::GB) -> i32 {
+    gb.rumble().into()
+}
+
+// nonzero to draw whole lines at once, see Renderer
+#[unsafe(no_mangle)]
+pub fn set_scanline_renderer(gb: &mut gb::GB, on: i32) {
+    gb.set_renderer(if on != 0 { Renderer::Scanline } else { Renderer::Fifo });
+}
+
+// polled by the host after each frame, nonzero while the game has the
+// LCD off and the frame buffer is blank
+#[unsafe(no_mangle)]
+pub fn get_lcd_off(gb: &gb::GB) -> i32 {
+    gb.lcd_off().into()
+}
+
+// budget bytes of history on top of two snapshots, one every interval
+// frames, which is also how far each step back goes; see Rewind
+#[unsafe(no_mangle)]
+pub fn rewind_get(budget: usize, interval: u32) -> *mut Rewind {
+    Box::into_raw(Box::new(Rewind::new(budget, interval)))
+}
+
+// for a host done with a Rewind, e.g. to get one with another budget
+#[unsafe(no_mangle)]
+pub fn rewind_free(rewind: Box<Rewind>) {
+    drop(rewind);
+}
+
+#[unsafe(no_mangle)]
+pub fn rewind_push(rewind: &mut Rewind, gb: &gb::GB) {
+    rewind.push(gb);
+}
+
+// nonzero while there was something to step back to
+#[unsafe(no_mangle)]
+pub fn rewind_step_back(rewind: &mut Rewind, gb: &mut gb::GB) -> i32 {
+    rewind.step_back(gb).into()
 }
 
 #[unsafe(no_mangle)]
@@ -66,15 +298,8 @@ pub fn pause(gb: &mut gb::GB, val: i32) {
     gb.stack_dump();
 }
 
+// see GB::set_buttons for the bits, index.html maps keys onto them
 #[unsafe(no_mangle)]
 pub extern "C" fn write_button_state(gb: &mut gb::GB, info: usize) {
-    let previous_matrix = gb.bus.read_joystate();
-
-    gb.bus.joy_sel = info as u8 & 0x30;
-    let current_matrix = gb.bus.read_joystate();
-
-    let bits_changed = previous_matrix & !current_matrix & 0x0F;
-    if bits_changed != 0 {
-        gb.bus.intr.raise(intr::IntrSrc::Joypad);
-    }
+    gb.set_buttons(info as u8);
 }
@@ -0,0 +1,253 @@
+use alloc::boxed::Box;
+use alloc::vec;
+
+use crate::gb::GB;
+use crate::state::{STATE_MAX_SIZE, Writer};
+
+// history of snapshots taken every few frames, for stepping back in time
+//
+// only the newest snapshot is kept whole, every older one is stored as its
+// XOR against the snapshot after it, run length encoded; consecutive frames
+// differ in little more than the frame buffer, so most of a delta is zero
+// runs. stepping back XORs the newest delta into the newest snapshot, and
+// the oldest delta can be dropped to make room without touching the rest
+//
+// the buffers are all allocated up front, neither taking a snapshot every
+// frame nor stepping back allocates
+pub struct Rewind {
+    interval: u32, // frames between snapshots
+    frames: u32,   // frames since the newest snapshot
+
+    newest: Box<[u8]>,
+    scratch: Box<[u8]>,
+    len: usize, // of a snapshot, 0 before the first
+
+    // ring of deltas, each a u32 length, the encoded delta and the length
+    // again so that it can be walked from either end
+    ring: Box<[u8]>,
+    head: usize, // end of the newest delta
+    tail: usize, // start of the oldest delta
+    wrap: Option<usize>, // end of the older deltas once head went back to 0
+    count: usize,
+}
+
+impl Rewind {
+    // runs in a zero count as a 15 bit length, literal runs in a count
+    // byte below 0x80 followed by up to 0x80 bytes
+    const ZERO_RUN: u8 = 0x80;
+    const MAX_ZERO_RUN: usize = 0x8000;
+    const MAX_LITERAL: usize = 0x80;
+    // zeros shorter than this are cheaper left in a literal
+    const MIN_ZERO_RUN: usize = 3;
+
+    // budget is the memory for deltas, on top of two whole snapshots;
+    // stepping back goes a snapshot at a time, so interval frames at once
+    pub fn new(budget: usize, interval: u32) -> Rewind {
+        Rewind {
+            interval: interval.max(1),
+            frames: 0,
+            newest: vec![0; STATE_MAX_SIZE].into_boxed_slice(),
+            scratch: vec![0; STATE_MAX_SIZE].into_boxed_slice(),
+            len: 0,
+            ring: vec![0; budget].into_boxed_slice(),
+            head: 0,
+            tail: 0,
+            wrap: None,
+            count: 0,
+        }
+    }
+
+    // forget every snapshot, e.g. after loading another ROM or state
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.len = 0;
+        self.head = 0;
+        self.tail = 0;
+        self.wrap = None;
+        self.count = 0;
+    }
+
+    // how many snapshots there are to step back to
+    pub fn len(&self) -> usize {
+        if self.len == 0 { 0 } else { self.count + 1 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // to be called once per frame, taking a snapshot every interval frames
+    pub fn push(&mut self, gb: &GB) {
+        self.frames += 1;
+        if self.len != 0 && self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+
+        let Ok(len) = gb.save_state(&mut self.scratch) else {
+            self.clear();
+            return;
+        };
+        // another ROM went in, the history doesn't lead here
+        if len != self.len {
+            self.clear();
+            self.newest[..len].copy_from_slice(&self.scratch[..len]);
+            self.len = len;
+            return;
+        }
+
+        for (d, &s) in self.scratch[..len].iter_mut().zip(&self.newest[..len]) {
+            *d ^= s;
+        }
+        self.store_delta();
+        // the delta is still in scratch, which turns newest into the new snapshot
+        for (s, &d) in self.newest[..len].iter_mut().zip(&self.scratch[..len]) {
+            *s ^= d;
+        }
+    }
+
+    // goes back to the newest snapshot, or to the one before it, interval
+    // frames further back, if no frame ran since; false once there is nothing left, or if the snapshot
+    // doesn't fit the machine anymore and the history is dropped
+    pub fn step_back(&mut self, gb: &mut GB) -> bool {
+        if self.len == 0 {
+            return false;
+        }
+        if self.frames == 0 {
+            let Some(delta) = self.pop_delta() else {
+                return false;
+            };
+            Rewind::decode(&self.ring[delta.0..delta.1], &mut self.newest[..self.len]);
+        }
+        self.frames = 0;
+
+        // scratch holds nothing between calls, so it takes the backup
+        if gb.load_state_with(&self.newest[..self.len], &mut self.scratch).is_err() {
+            self.clear();
+            return false;
+        }
+        true
+    }
+
+    // encodes the delta in scratch into the ring, dropping the oldest ones
+    // until it fits; if it can't fit at all, the history starts over
+    fn store_delta(&mut self) {
+        let size = {
+            let mut w = Writer::new(&mut []);
+            Rewind::encode(&self.scratch[..self.len], &mut w);
+            w.len()
+        };
+
+        let Some(start) = self.reserve(size + 8) else {
+            self.head = 0;
+            self.tail = 0;
+            self.wrap = None;
+            self.count = 0;
+            return;
+        };
+        let entry = &mut self.ring[start..start + size + 8];
+        entry[..4].copy_from_slice(&(size as u32).to_le_bytes());
+        Rewind::encode(&self.scratch[..self.len], &mut Writer::new(&mut entry[4..4 + size]));
+        entry[4 + size..].copy_from_slice(&(size as u32).to_le_bytes());
+
+        self.head = start + size + 8;
+        self.count += 1;
+    }
+
+    fn read_len(&self, at: usize) -> usize {
+        u32::from_le_bytes(self.ring[at..at + 4].try_into().unwrap()) as usize
+    }
+
+    // where the next size bytes go
+    fn reserve(&mut self, size: usize) -> Option<usize> {
+        if size > self.ring.len() {
+            return None;
+        }
+        loop {
+            if self.count == 0 {
+                self.head = 0;
+                self.tail = 0;
+                self.wrap = None;
+                return Some(0);
+            }
+            match self.wrap {
+                None if self.ring.len() - self.head >= size => return Some(self.head),
+                None if self.tail >= size => {
+                    self.wrap = Some(self.head);
+                    return Some(0);
+                }
+                Some(_) if self.tail - self.head >= size => return Some(self.head),
+                _ => self.drop_oldest(),
+            }
+        }
+    }
+
+    fn drop_oldest(&mut self) {
+        self.tail += self.read_len(self.tail) + 8;
+        self.count -= 1;
+        if self.wrap == Some(self.tail) {
+            self.tail = 0;
+            self.wrap = None;
+        }
+    }
+
+    // range of the encoded newest delta in the ring
+    fn pop_delta(&mut self) -> Option<(usize, usize)> {
+        if self.count == 0 {
+            return None;
+        }
+        if self.head == 0 {
+            self.head = self.wrap.take().unwrap();
+        }
+        let end = self.head - 4;
+        let start = end - self.read_len(end);
+        self.head = start - 4;
+        self.count -= 1;
+        Some((start, end))
+    }
+
+    fn encode(src: &[u8], w: &mut Writer) {
+        let mut i = 0;
+        while i < src.len() {
+            let zeros = src[i..].iter().take(Rewind::MAX_ZERO_RUN).take_while(|&&b| b == 0).count();
+            if zeros >= Rewind::MIN_ZERO_RUN || i + zeros == src.len() {
+                let run = zeros - 1;
+                w.bytes(&[Rewind::ZERO_RUN | (run >> 8) as u8, run as u8]);
+                i += zeros;
+                continue;
+            }
+
+            // up to the next run of zeros worth breaking the literal for
+            let mut end = i;
+            while end < src.len() && end - i < Rewind::MAX_LITERAL {
+                let zeros = src[end..].iter().take(Rewind::MIN_ZERO_RUN).take_while(|&&b| b == 0).count();
+                if zeros == Rewind::MIN_ZERO_RUN {
+                    break;
+                }
+                end += zeros.max(1);
+            }
+            let end = end.min(i + Rewind::MAX_LITERAL);
+            w.u8((end - i - 1) as u8);
+            w.bytes(&src[i..end]);
+            i = end;
+        }
+    }
+
+    // XORs an encoded delta into dst
+    fn decode(mut src: &[u8], dst: &mut [u8]) {
+        let mut i = 0;
+        while let [count, rest @ ..] = src {
+            if count & Rewind::ZERO_RUN != 0 {
+                i += (usize::from(count & !Rewind::ZERO_RUN) << 8 | usize::from(rest[0])) + 1;
+                src = &rest[1..];
+            } else {
+                let len = usize::from(*count) + 1;
+                for (d, s) in dst[i..i + len].iter_mut().zip(&rest[..len]) {
+                    *d ^= s;
+                }
+                i += len;
+                src = &rest[len..];
+            }
+        }
+    }
+}
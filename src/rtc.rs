@@ -0,0 +1,229 @@
+use crate::state::{Reader, State, StateError, Writer};
+
+// size of the clock footer of .sav files and BESS states, see Rtc::footer
+pub const RTC_FOOTER_LEN: usize = 0x30;
+
+// MBC3 real-time clock, driven by emulated time rather than the host clock;
+// only while the game isn't running, between a save and its load, does
+// host time move it on
+// see https://gbdev.io/pandocs/MBC3.html
+#[derive(Clone, Copy)]
+struct RtcRegs {
+    s: u8,
+    m: u8,
+    h: u8,
+    dl: u8,
+    dh: u8, // bit 0 day counter bit 8, bit 6 halt, bit 7 day carry
+}
+
+pub(crate) struct Rtc {
+    live: RtcRegs,
+    latched: RtcRegs,
+
+    clock: u32, // M-cycles into the current second
+    latch: u8,  // last value written to the latch register
+}
+
+impl Rtc {
+    const DH_DAY_HIGH: u8 = 0x01;
+    const DH_HALT: u8 = 0x40;
+    const DH_CARRY: u8 = 0x80;
+
+    // the 32768 Hz crystal ticks once per 2^20 M-cycles
+    const SECOND_CYCLES: u32 = 1 << 20;
+
+    pub const fn init(&mut self) {
+        self.latch = 0xFF;
+    }
+
+    // the clock as a freshly inserted cart has it
+    pub fn reset(&mut self) {
+        let zero = RtcRegs { s: 0, m: 0, h: 0, dl: 0, dh: 0 };
+        self.live = zero;
+        self.latched = zero;
+        self.clock = 0;
+        self.latch = 0xFF;
+    }
+
+    // writing 00 then 01 copies the live clock into the readable registers
+    pub fn write_latch(&mut self, val: u8) {
+        if self.latch == 0x00 && val == 0x01 {
+            self.latched = self.live;
+        }
+        self.latch = val;
+    }
+
+    // unused bits read back as 1
+    pub fn read(&self, reg: u8) -> u8 {
+        let r = &self.latched;
+        match reg {
+            0x08 => r.s | 0xC0,
+            0x09 => r.m | 0xC0,
+            0x0A => r.h | 0xE0,
+            0x0B => r.dl,
+            0x0C => r.dh | 0x3E,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, reg: u8, val: u8) {
+        let r = &mut self.live;
+        match reg {
+            0x08 => {
+                r.s = val & 0x3F;
+                self.clock = 0;
+            }
+            0x09 => r.m = val & 0x3F,
+            0x0A => r.h = val & 0x1F,
+            0x0B => r.dl = val,
+            0x0C => r.dh = val & (Rtc::DH_DAY_HIGH | Rtc::DH_HALT | Rtc::DH_CARRY),
+            _ => {}
+        }
+    }
+
+    // live and then latched registers as 32 bit integers, then now, the
+    // Unix time; the footer VBA and BGB put after the RAM in .sav files,
+    // which BESS uses as well
+    pub fn footer(&self, now: u64) -> [u8; RTC_FOOTER_LEN] {
+        let mut footer = [0; RTC_FOOTER_LEN];
+        let mut w = Writer::new(&mut footer);
+        for reg in self.live.to_bytes().into_iter().chain(self.latched.to_bytes()) {
+            w.u32(reg.into());
+        }
+        w.u64(now);
+        footer
+    }
+
+    // starts the current second over, then runs the clock on by the time
+    // since the footer was saved, unless that is unknown (0) or halted
+    pub fn set_footer(&mut self, footer: &[u8; RTC_FOOTER_LEN], now: u64) {
+        let reg = |i: usize| footer[i * 4];
+        self.live = RtcRegs::from_bytes([0, 1, 2, 3, 4].map(reg));
+        self.latched = RtcRegs::from_bytes([5, 6, 7, 8, 9].map(reg));
+        self.clock = 0;
+        let saved = u64::from_le_bytes(footer[0x28..].try_into().unwrap());
+        if saved != 0 {
+            self.advance(now.saturating_sub(saved));
+        }
+    }
+
+    // seconds at once rather than one by one
+    fn advance(&mut self, mut seconds: u64) {
+        if self.live.dh & Rtc::DH_HALT != 0 {
+            return;
+        }
+        // counters set out of range first have to wrap around
+        while seconds > 0 && !self.live.in_range() {
+            self.inc_second();
+            seconds -= 1;
+        }
+
+        let r = &mut self.live;
+        let days = u64::from(r.dh & Rtc::DH_DAY_HIGH) << 8 | u64::from(r.dl);
+        let time = ((days * 24 + u64::from(r.h)) * 60 + u64::from(r.m)) * 60 + u64::from(r.s);
+        let time = time.saturating_add(seconds);
+        r.s = (time % 60) as u8;
+        r.m = (time / 60 % 60) as u8;
+        r.h = (time / 3600 % 24) as u8;
+        let days = time / 86400;
+        if days > 0x1FF {
+            r.dh |= Rtc::DH_CARRY;
+        }
+        r.dl = days as u8;
+        r.dh = r.dh & !Rtc::DH_DAY_HIGH | (days >> 8) as u8 & Rtc::DH_DAY_HIGH;
+    }
+
+    pub fn tick(&mut self) {
+        if self.live.dh & Rtc::DH_HALT != 0 {
+            return;
+        }
+
+        self.clock += 1;
+        if self.clock < Rtc::SECOND_CYCLES {
+            return;
+        }
+        self.clock = 0;
+        self.inc_second();
+    }
+
+    // each counter wraps at its own width when set out of range,
+    // without carrying into the next one
+    fn inc_second(&mut self) {
+        let r = &mut self.live;
+
+        r.s = (r.s + 1) & 0x3F;
+        if r.s != 60 {
+            return;
+        }
+        r.s = 0;
+
+        r.m = (r.m + 1) & 0x3F;
+        if r.m != 60 {
+            return;
+        }
+        r.m = 0;
+
+        r.h = (r.h + 1) & 0x1F;
+        if r.h != 24 {
+            return;
+        }
+        r.h = 0;
+
+        let (dl, overflow) = r.dl.overflowing_add(1);
+        r.dl = dl;
+        if overflow {
+            if r.dh & Rtc::DH_DAY_HIGH != 0 {
+                r.dh |= Rtc::DH_CARRY;
+            }
+            r.dh ^= Rtc::DH_DAY_HIGH;
+        }
+    }
+}
+
+impl RtcRegs {
+    fn to_bytes(self) -> [u8; 5] {
+        [self.s, self.m, self.h, self.dl, self.dh]
+    }
+
+    fn in_range(&self) -> bool {
+        self.s < 60 && self.m < 60 && self.h < 24
+    }
+
+    fn from_bytes([s, m, h, dl, dh]: [u8; 5]) -> RtcRegs {
+        RtcRegs {
+            s: s & 0x3F,
+            m: m & 0x3F,
+            h: h & 0x1F,
+            dl,
+            dh: dh & (Rtc::DH_DAY_HIGH | Rtc::DH_HALT | Rtc::DH_CARRY),
+        }
+    }
+
+    fn save(&self, w: &mut Writer) {
+        w.bytes(&self.to_bytes());
+    }
+
+    fn load(r: &mut Reader) -> Result<RtcRegs, StateError> {
+        Ok(RtcRegs::from_bytes(r.take(5)?.try_into().unwrap()))
+    }
+}
+
+impl State for Rtc {
+    fn save(&self, w: &mut Writer) {
+        self.live.save(w);
+        self.latched.save(w);
+        w.u32(self.clock);
+        w.u8(self.latch);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.live = RtcRegs::load(r)?;
+        self.latched = RtcRegs::load(r)?;
+        self.clock = r.u32()?;
+        self.latch = r.u8()?;
+        if self.clock >= Rtc::SECOND_CYCLES {
+            return Err(StateError::Invalid);
+        }
+        Ok(())
+    }
+}
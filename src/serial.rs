@@ -0,0 +1,87 @@
+use crate::intr::{Intr, IntrSrc};
+use crate::state::{Reader, State, StateError, Writer};
+
+// no link partner is ever connected, so every received bit is 1
+pub(crate) struct Serial {
+    pub sb: u8,
+    pub sc: u8,
+
+    clock: u8, // M-cycles into the current bit
+    shift: u8, // bits left in the current transfer
+    tx: u8,    // byte being sent out
+
+    pub(crate) out: Option<u8>,
+}
+
+impl Serial {
+    const SC_TRANSFER: u8 = 0x80;
+    const SC_INTERNAL_CLOCK: u8 = 0x01;
+
+    // internal clock is 8192 Hz, one bit every 128 M-cycles
+    const BIT_CYCLES: u8 = 128;
+
+    pub const fn init(&mut self) {
+        self.sc = 0x7E;
+    }
+
+    pub fn read_sc(&self) -> u8 {
+        self.sc | 0x7E
+    }
+
+    pub fn write_sc(&mut self, val: u8) {
+        self.sc = val | 0x7E;
+        if val & Serial::SC_TRANSFER != 0 {
+            self.clock = 0;
+            self.shift = 8;
+            self.tx = self.sb;
+        }
+    }
+
+    pub fn tick(&mut self, intr: &mut Intr) {
+        // an externally clocked transfer never completes without a partner
+        if self.sc & (Serial::SC_TRANSFER | Serial::SC_INTERNAL_CLOCK)
+            != Serial::SC_TRANSFER | Serial::SC_INTERNAL_CLOCK
+        {
+            return;
+        }
+
+        self.clock += 1;
+        if self.clock < Serial::BIT_CYCLES {
+            return;
+        }
+        self.clock = 0;
+
+        self.sb = self.sb << 1 | 0x01;
+        self.shift -= 1;
+        if self.shift == 0 {
+            self.sc &= !Serial::SC_TRANSFER;
+            self.out = Some(self.tx);
+            intr.raise(IntrSrc::Serial);
+        }
+    }
+}
+
+impl State for Serial {
+    fn save(&self, w: &mut Writer) {
+        w.u8(self.sb);
+        w.u8(self.sc);
+        w.u8(self.clock);
+        w.u8(self.shift);
+        w.u8(self.tx);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.sb = r.u8()?;
+        self.sc = r.u8()?;
+        self.clock = r.u8()?;
+        self.shift = r.u8()?;
+        self.tx = r.u8()?;
+        // a running transfer has bits left, the last one ends it
+        let running = self.sc & Serial::SC_TRANSFER != 0;
+        if self.clock >= Serial::BIT_CYCLES || self.shift > 8 || running && self.shift == 0 {
+            return Err(StateError::Invalid);
+        }
+        self.out = None;
+        Ok(())
+    }
+}
@@ -0,0 +1,154 @@
+use core::fmt;
+
+// machine snapshots: a magic and version, then every part of the machine
+// in a fixed order, integers little endian; bump STATE_VERSION whenever the
+// layout of any part changes
+pub const STATE_VERSION: u32 = 8;
+const MAGIC: [u8; 4] = *b"GBRS";
+
+// upper bound on the size of a snapshot, the cart RAM being the bulk of it
+pub const STATE_MAX_SIZE: usize = 0x40000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    BufferTooSmall(usize), // bytes needed
+    BadMagic,
+    Version(u32),
+    WrongRom,
+    Length { expected: usize, actual: usize },
+    Invalid, // an out of range value
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BufferTooSmall(len) => write!(f, "state needs a {len} byte buffer"),
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::Version(v) => write!(f, "save state version {v}, expected {STATE_VERSION}"),
+            StateError::WrongRom => write!(f, "save state is for another ROM"),
+            StateError::Length { expected, actual } => {
+                write!(f, "save state is {actual} bytes, expected {expected}")
+            }
+            StateError::Invalid => write!(f, "corrupt save state"),
+        }
+    }
+}
+
+impl core::error::Error for StateError {}
+
+// keeps counting past the end of the buffer, so that a short or empty
+// one tells how much space is needed
+pub(crate) struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Writer<'a> {
+        Writer { buf, pos: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.pos
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) {
+        if let Some(dst) = self.buf.get_mut(self.pos..self.pos + data.len()) {
+            dst.copy_from_slice(data);
+        }
+        self.pos += data.len();
+    }
+
+    pub fn u8(&mut self, val: u8) {
+        self.bytes(&[val]);
+    }
+
+    pub fn bool(&mut self, val: bool) {
+        self.u8(val.into());
+    }
+
+    pub fn u16(&mut self, val: u16) {
+        self.bytes(&val.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, val: u32) {
+        self.bytes(&val.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, val: u64) {
+        self.bytes(&val.to_le_bytes());
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Reader<'a> {
+        Reader { buf, pos: 0 }
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        let data = self.buf.get(self.pos..self.pos + len).ok_or(StateError::Invalid)?;
+        self.pos += len;
+        Ok(data)
+    }
+
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+// implemented by every part of the machine, only covering emulated
+// state and not host settings such as the LD B, B breakpoint
+pub(crate) trait State {
+    fn save(&self, w: &mut Writer);
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError>;
+}
+
+pub(crate) fn save_header(w: &mut Writer, rom_id: &[u8]) {
+    w.bytes(&MAGIC);
+    w.u32(STATE_VERSION);
+    w.bytes(rom_id);
+}
+
+pub(crate) fn load_header(r: &mut Reader, rom_id: &[u8]) -> Result<(), StateError> {
+    if r.take(MAGIC.len()).map_err(|_| StateError::BadMagic)? != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    let version = r.u32().map_err(|_| StateError::BadMagic)?;
+    if version != STATE_VERSION {
+        return Err(StateError::Version(version));
+    }
+    if r.take(rom_id.len()).map_err(|_| StateError::WrongRom)? != rom_id {
+        return Err(StateError::WrongRom);
+    }
+    Ok(())
+}
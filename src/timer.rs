@@ -1,31 +1,104 @@
 use crate::intr::{Intr, IntrSrc};
+use crate::state::{Reader, State, StateError, Writer};
 
+// see https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
 pub(crate) struct Timer {
-    pub div: u8,
+    counter: u16, // system counter, DIV is the upper 8 bits
     pub tima: u8,
     pub tma: u8,
     pub tac: u8,
 
-    tick_count: u8,
+    reload: bool, // TIMA overflowed last M-cycle
 }
 
 impl Timer {
+    const TAC_ENABLE: u8 = 0x04;
+
     pub const fn init(&mut self) { }
 
-    pub fn tick(&mut self, intr: &mut Intr) {
-        self.tick_count = self.tick_count.wrapping_add(1);
+    // the counter bit whose falling edge increments TIMA
+    fn input(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            3 => 7,
+            _ => unreachable!(),
+        };
+        self.tac & Timer::TAC_ENABLE != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn inc_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.reload = overflow;
+    }
+
+    // for starting up in the state a boot ROM leaves behind
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
 
-        if self.tick_count % 64 == 0 {
-            self.div = self.div.wrapping_add(1);
+    pub fn read_div(&self) -> u8 {
+        (self.counter >> 8) as u8
+    }
+
+    pub fn write_div(&mut self) {
+        let old = self.input();
+        self.counter = 0;
+        if old {
+            self.inc_tima();
         }
+    }
 
-        if self.tac & 0x40 != 0 && self.tick_count % (4 << ((self.tac - 1) & 0x3) * 2) == 0 {
-            if self.tima == 0xFF {
-                self.tima = self.tma;
-                intr.raise(IntrSrc::Timer);
-            } else {
-                self.tima += 1;
-            }
+    pub fn write_tima(&mut self, val: u8) {
+        // writing in the M-cycle after an overflow cancels the reload
+        self.tima = val;
+        self.reload = false;
+    }
+
+    pub fn read_tac(&self) -> u8 {
+        self.tac | 0xF8
+    }
+
+    pub fn write_tac(&mut self, val: u8) {
+        let old = self.input();
+        self.tac = val & 0x07;
+        if old && !self.input() {
+            self.inc_tima();
+        }
+    }
+
+    pub fn tick(&mut self, intr: &mut Intr) {
+        if self.reload {
+            self.reload = false;
+            self.tima = self.tma;
+            intr.raise(IntrSrc::Timer);
+        }
+
+        let old = self.input();
+        self.counter = self.counter.wrapping_add(4);
+        if old && !self.input() {
+            self.inc_tima();
         }
     }
 }
+
+impl State for Timer {
+    fn save(&self, w: &mut Writer) {
+        w.u16(self.counter);
+        w.u8(self.tima);
+        w.u8(self.tma);
+        w.u8(self.tac);
+        w.bool(self.reload);
+    }
+
+    fn load(&mut self, r: &mut Reader) -> Result<(), StateError> {
+        self.counter = r.u16()?;
+        self.tima = r.u8()?;
+        self.tma = r.u8()?;
+        self.tac = r.u8()? & 0x07;
+        self.reload = r.bool()?;
+        Ok(())
+    }
+}
@@ -57,46 +57,121 @@ impl Write for WasmWriter {
     }
 }
 
+// first fit over a list of free blocks kept in address order, so that a
+// freed block merges with free neighbours again; the memory only grows,
+// by whole pages, when no free block fits
+//
+// every block starts with its size and is a multiple of ALIGN, the word
+// before the pointer handed out leads back to the start of its block
 #[global_allocator]
-pub static ALLOCATOR: Allocator = Allocator { used: Cell::new(false) };
+pub static ALLOCATOR: Allocator = Allocator { free: Cell::new(ptr::null_mut()) };
+// wasm32 has a single thread
 unsafe impl Sync for Allocator {}
 
 pub struct Allocator {
-    used: Cell<bool>,
+    free: Cell<*mut FreeBlock>,
 }
 
-const WASM_MEM_BLOCK_SIZE: usize = 0x10000; // 64 Ki
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
 
-unsafe impl GlobalAlloc for Allocator {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if self.used.replace(true) {
-            return ptr::null_mut()
+const WASM_MEM_BLOCK_SIZE: usize = 0x10000; // 64 Ki
+const ALIGN: usize = 16;
+// room for the size and the back pointer in front of the data
+const HEADER: usize = 16;
+
+impl Allocator {
+    // takes a free block of at least size bytes off the list, splitting
+    // off what is left if that is worth keeping
+    unsafe fn take(&self, size: usize) -> *mut u8 {
+        let mut link = self.free.as_ptr();
+        unsafe {
+            while !(*link).is_null() {
+                let block = *link;
+                if (*block).size >= size {
+                    if (*block).size - size >= HEADER + ALIGN {
+                        let rest = block.byte_add(size);
+                        (*rest).size = (*block).size - size;
+                        (*rest).next = (*block).next;
+                        (*block).size = size;
+                        *link = rest;
+                    } else {
+                        *link = (*block).next;
+                    }
+                    return block.cast();
+                }
+                link = &raw mut (*block).next;
+            }
         }
+        ptr::null_mut()
+    }
 
-        let size = layout.size();
+    // puts a block back on the list, merged with the blocks around it
+    unsafe fn give(&self, block: *mut u8, size: usize) {
+        let block = block.cast::<FreeBlock>();
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.free.get();
+        unsafe {
+            while !next.is_null() && next < block {
+                prev = next;
+                next = (*next).next;
+            }
 
-        if size == 0 {
-            return ptr::null_mut()
-        }
+            (*block).size = size;
+            (*block).next = next;
+            if !next.is_null() && block.byte_add(size) == next {
+                (*block).size += (*next).size;
+                (*block).next = (*next).next;
+            }
 
-        let delta_pages = size.div_ceil(WASM_MEM_BLOCK_SIZE);
-        let prev_pages = memory_grow(0, delta_pages);
+            if prev.is_null() {
+                self.free.set(block);
+            } else if prev.byte_add((*prev).size) == block {
+                (*prev).size += (*block).size;
+                (*prev).next = (*block).next;
+            } else {
+                (*prev).next = block;
+            }
+        }
+    }
+}
 
-        if prev_pages == usize::MAX {
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // over-allocate for alignments past that of the blocks
+        let extra = layout.align().saturating_sub(ALIGN);
+        let Some(size) = (HEADER + extra)
+            .checked_add(layout.size())
+            .and_then(|size| size.checked_next_multiple_of(ALIGN))
+        else {
             return ptr::null_mut();
         };
 
-        let addr = prev_pages * WASM_MEM_BLOCK_SIZE;
-
-        if addr % layout.align() != 0 {
-            self.used.set(false);
-            return ptr::null_mut();
-        };
+        unsafe {
+            let mut block = self.take(size);
+            if block.is_null() {
+                let pages = size.div_ceil(WASM_MEM_BLOCK_SIZE);
+                let prev_pages = memory_grow(0, pages);
+                if prev_pages == usize::MAX {
+                    return ptr::null_mut();
+                }
+                self.give((prev_pages * WASM_MEM_BLOCK_SIZE) as *mut u8, pages * WASM_MEM_BLOCK_SIZE);
+                block = self.take(size);
+            }
 
-        addr as *mut u8
+            let data = block.add(HEADER).map_addr(|addr| addr.next_multiple_of(layout.align()));
+            data.cast::<*mut u8>().sub(1).write(block);
+            data
+        }
     }
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
+        unsafe {
+            let block = ptr.cast::<*mut u8>().sub(1).read();
+            self.give(block, block.cast::<FreeBlock>().read().size);
+        }
     }
 }
 
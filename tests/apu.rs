@@ -0,0 +1,23 @@
+// APU registers checked with hand assembled ROMs that stop at LD B, B
+
+mod common;
+
+#[test]
+fn unused_registers_read_ff() {
+    // the holes between the channel registers ignore writes and read $FF;
+    // the reads are ANDed together in D
+    let mut prog = vec![0x16, 0xFF]; // ld d, $FF
+    for reg in [0x15, 0x1F, 0x27, 0x2F] {
+        #[rustfmt::skip]
+        prog.extend_from_slice(&[
+            0xAF,       // xor a
+            0xE0, reg,  // ldh [reg], a
+            0xF0, reg,  // ldh a, [reg]
+            0xA2,       // and d
+            0x57,       // ld d, a
+        ]);
+    }
+    prog.push(0x40); // ld b, b
+    let regs = common::run_to_break(&common::program_rom(&prog, &[]), 1);
+    assert_eq!(regs.de >> 8, 0xFF, "{regs:04X?}");
+}
@@ -0,0 +1,173 @@
+// Blargg's test ROMs, which report their results through the serial port
+// and as text on the background map, e.g.
+// tests/roms/blargg/cpu_instrs/individual/01-special.gb
+
+mod common;
+
+use gb_rs::{CYCLES_PER_FRAME, GB};
+
+#[derive(Debug, PartialEq)]
+enum Verdict {
+    Passed,
+    Failed,
+    Timeout,
+}
+
+struct Report {
+    verdict: Verdict,
+    serial: String,
+    screen: String,
+}
+
+// the test shell loads its font so that tile numbers are ASCII codes
+fn screen_text(gb: &GB) -> String {
+    let map = &gb.vram()[0x1800..0x1C00];
+    map.chunks_exact(32)
+        .map(|row| {
+            row.iter()
+                .map(|&t| if (0x20..0x7F).contains(&t) { t as char } else { ' ' })
+                .collect::<String>()
+                .trim_end()
+                .to_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_owned()
+}
+
+fn verdict(text: &str) -> Option<Verdict> {
+    if text.contains("Passed") {
+        Some(Verdict::Passed)
+    } else if text.contains("Failed") {
+        Some(Verdict::Failed)
+    } else {
+        None
+    }
+}
+
+fn run(rom: &[u8], max_frames: usize) -> Report {
+    // frames to keep running after a verdict so the details get printed
+    const TRAILING_FRAMES: usize = 30;
+
+    let mut gb = GB::new();
+    gb.load_rom(rom).expect("failed to load ROM");
+
+    let mut serial = String::new();
+    let mut result = None;
+    let mut deadline = max_frames;
+    let mut frame = 0;
+    while frame < deadline {
+        for _ in 0..CYCLES_PER_FRAME {
+            if gb.tick().is_break() {
+                break;
+            }
+            if let Some(b) = gb.take_serial() {
+                serial.push(b as char);
+            }
+        }
+        frame += 1;
+
+        if result.is_none() {
+            result = verdict(&serial).or_else(|| verdict(&screen_text(&gb)));
+            if result.is_some() {
+                deadline = frame + TRAILING_FRAMES;
+            }
+        }
+    }
+
+    Report {
+        verdict: result.unwrap_or(Verdict::Timeout),
+        serial,
+        screen: screen_text(&gb),
+    }
+}
+
+// "01:ok  02:01  03:ok ..." as printed by the combined ROMs
+fn sub_results(text: &str) -> Vec<(&str, bool)> {
+    text.split_whitespace()
+        .filter_map(|tok| tok.split_once(':'))
+        .filter(|(num, res)| num.len() == 2 && !res.is_empty() && num.bytes().all(|b| b.is_ascii_digit()))
+        .map(|(num, res)| (num, res == "ok"))
+        .collect()
+}
+
+fn check(rel: &str, max_frames: usize) {
+    let rom = common::load_rom("blargg", rel);
+
+    let report = run(&rom, max_frames);
+    let text = if report.serial.is_empty() { &report.screen } else { &report.serial };
+
+    for (num, ok) in sub_results(text) {
+        eprintln!("{rel} #{num}: {}", if ok { "ok" } else { "FAILED" });
+    }
+    assert_eq!(
+        report.verdict,
+        Verdict::Passed,
+        "{rel}\n--- serial ---\n{}\n--- screen ---\n{}",
+        report.serial,
+        report.screen
+    );
+}
+
+macro_rules! blargg_tests {
+    ($($name:ident: $rom:literal, $frames:literal;)*) => {
+        $(
+            #[test]
+            #[ignore = "needs the Blargg ROMs, see tests/common"]
+            fn $name() {
+                check($rom, $frames);
+            }
+        )*
+    };
+}
+
+blargg_tests! {
+    cpu_instrs: "cpu_instrs/cpu_instrs.gb", 4000;
+    cpu_instrs_01_special: "cpu_instrs/individual/01-special.gb", 600;
+    cpu_instrs_02_interrupts: "cpu_instrs/individual/02-interrupts.gb", 600;
+    cpu_instrs_03_op_sp_hl: "cpu_instrs/individual/03-op sp,hl.gb", 600;
+    cpu_instrs_04_op_r_imm: "cpu_instrs/individual/04-op r,imm.gb", 600;
+    cpu_instrs_05_op_rp: "cpu_instrs/individual/05-op rp.gb", 600;
+    cpu_instrs_06_ld_r_r: "cpu_instrs/individual/06-ld r,r.gb", 600;
+    cpu_instrs_07_jr_jp_call_ret_rst: "cpu_instrs/individual/07-jr,jp,call,ret,rst.gb", 600;
+    cpu_instrs_08_misc_instrs: "cpu_instrs/individual/08-misc instrs.gb", 600;
+    cpu_instrs_09_op_r_r: "cpu_instrs/individual/09-op r,r.gb", 1200;
+    cpu_instrs_10_bit_ops: "cpu_instrs/individual/10-bit ops.gb", 1200;
+    cpu_instrs_11_op_a_hl: "cpu_instrs/individual/11-op a,(hl).gb", 1200;
+    instr_timing: "instr_timing/instr_timing.gb", 600;
+    mem_timing: "mem_timing/mem_timing.gb", 600;
+    mem_timing_01_read_timing: "mem_timing/individual/01-read_timing.gb", 600;
+    mem_timing_02_write_timing: "mem_timing/individual/02-write_timing.gb", 600;
+    mem_timing_03_modify_timing: "mem_timing/individual/03-modify_timing.gb", 600;
+}
+
+// a hand assembled ROM that prints "Passed" the way the test shell does,
+// so the harness itself is exercised even without any ROMs around
+#[test]
+fn harness_reads_serial() {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // nop; jp $0150
+    #[rustfmt::skip]
+    let prog = [
+        0x21, 0x67, 0x01, // ld hl, msg
+        0x2A,             // loop: ld a, [hl+]
+        0xB7,             // or a
+        0x28, 0x0E,       // jr z, done
+        0xE0, 0x01,       // ldh [SB], a
+        0x3E, 0x81,       // ld a, $81
+        0xE0, 0x02,       // ldh [SC], a
+        0xF0, 0x02,       // wait: ldh a, [SC]
+        0xE6, 0x80,       // and $80
+        0x20, 0xFA,       // jr nz, wait
+        0x18, 0xEE,       // jr loop
+        0x18, 0xFE,       // done: jr done
+    ];
+    rom[0x150..0x150 + prog.len()].copy_from_slice(&prog);
+    rom[0x167..0x167 + 8].copy_from_slice(b"Passed\n\0");
+    common::fix_header(&mut rom);
+
+    let report = run(&rom, 10);
+    assert_eq!(report.verdict, Verdict::Passed);
+    assert_eq!(report.serial, "Passed\n");
+}
@@ -0,0 +1,132 @@
+// carts checked with hand assembled ROMs that stop at LD B, B
+
+mod common;
+
+use gb_rs::{CYCLES_PER_FRAME, GB, LoadError, RTC_FOOTER_LEN, STATE_MAX_SIZE};
+
+// an MBC1 cart of len bytes, each 16 KiB bank filled with its number past
+// the program, reading the last byte of bank 3 and of bank 7 into D and E
+fn mbc1_rom(len: usize, rom_size: u8) -> Vec<u8> {
+    #[rustfmt::skip]
+    let prog = [
+        0x3E, 0x03,       // ld a, 3
+        0xEA, 0x00, 0x20, // ld [$2000], a
+        0xFA, 0xFF, 0x7F, // ld a, [$7FFF]
+        0x57,             // ld d, a
+        0x3E, 0x07,       // ld a, 7
+        0xEA, 0x00, 0x20, // ld [$2000], a
+        0xFA, 0xFF, 0x7F, // ld a, [$7FFF]
+        0x5F,             // ld e, a
+        0x40,             // ld b, b
+    ];
+    let mut rom = common::program_rom(&prog, &[]);
+    rom.resize(len, 0);
+    for (bank, data) in rom.chunks_mut(0x4000).enumerate().skip(1) {
+        data.fill(bank as u8);
+    }
+    rom[0x147] = 0x01; // MBC1
+    rom[0x148] = rom_size;
+    common::fix_header(&mut rom);
+    rom
+}
+
+#[test]
+fn images_end_in_erased_flash() {
+    // 2.5 banks of a 64 KiB cart, bank 3 repeats bank 2 and its missing
+    // second half reads FF, as does bank 7 masked down to bank 3
+    let regs = common::run_to_break(&mbc1_rom(0xA000, 0x01), 1);
+    assert_eq!(regs.de, 0xFFFF, "{regs:04X?}");
+}
+
+#[test]
+fn images_repeat_past_their_end() {
+    // 6 banks with the header admitting 2, mirrored out to 8 so that
+    // bank 7 is bank 5 again
+    let regs = common::run_to_break(&mbc1_rom(0x18000, 0x00), 1);
+    assert_eq!(regs.de, 0x0305, "{regs:04X?}");
+}
+
+#[test]
+fn bad_images_leave_the_cart_inserted() {
+    let mut gb = GB::new();
+    gb.load_rom(&mbc1_rom(0x10000, 0x01)).unwrap();
+    let mut bad = mbc1_rom(0x10000, 0x01);
+    bad[0x147] = 0xEE;
+    common::fix_header(&mut bad);
+    assert_eq!(gb.load_rom(&bad), Err(LoadError::UnknownCartType(0xEE)));
+    assert_eq!(gb.load_rom(&bad[..0x100]), Err(LoadError::TooSmall(0x100)));
+
+    gb.set_ld_b_b_break(true);
+    assert!((0..CYCLES_PER_FRAME).any(|_| gb.tick().is_break()), "breakpoint not reached");
+    assert_eq!(gb.registers().de, 0x0303);
+}
+
+// MBC3+TIMER, latching the clock and reading seconds, minutes, hours and
+// the low day count into E, D, L and H; unused bits read 1
+fn rtc_rom() -> Vec<u8> {
+    #[rustfmt::skip]
+    let mut prog = vec![
+        0x3E, 0x0A,       // ld a, $0A
+        0xEA, 0x00, 0x00, // ld [$0000], a
+        0xAF,             // xor a
+        0xEA, 0x00, 0x60, // ld [$6000], a
+        0x3C,             // inc a
+        0xEA, 0x00, 0x60, // ld [$6000], a
+    ];
+    for (reg, dst) in [(0x08, 0x5F), (0x09, 0x57), (0x0A, 0x6F), (0x0B, 0x67)] {
+        #[rustfmt::skip]
+        prog.extend_from_slice(&[
+            0x3E, reg,        // ld a, reg
+            0xEA, 0x00, 0x40, // ld [$4000], a
+            0xFA, 0x00, 0xA0, // ld a, [$A000]
+            dst,              // ld e/d/l/h, a
+        ]);
+    }
+    prog.push(0x40); // ld b, b
+    let mut rom = common::program_rom(&prog, &[]);
+    rom[0x147] = 0x0F; // MBC3+TIMER+BATTERY
+    common::fix_header(&mut rom);
+    rom
+}
+
+fn clock_after(load: impl FnOnce(&mut GB)) -> (u16, u16) {
+    let mut gb = GB::new();
+    gb.load_rom(&rtc_rom()).unwrap();
+    load(&mut gb);
+    gb.set_ld_b_b_break(true);
+    assert!((0..CYCLES_PER_FRAME).any(|_| gb.tick().is_break()), "breakpoint not reached");
+    (gb.registers().hl, gb.registers().de)
+}
+
+const SAVED: u64 = 1_700_000_000;
+// 3 days, 1 hour, 2 minutes and 3 seconds later
+const LATER: u64 = SAVED + ((3 * 24 + 1) * 60 + 2) * 60 + 3;
+
+#[test]
+fn clock_catches_up_with_the_save_footer() {
+    let mut gb = GB::new();
+    gb.load_rom(&rtc_rom()).unwrap();
+    let footer = gb.save_rtc(SAVED).expect("no clock");
+    assert_eq!(footer.len(), RTC_FOOTER_LEN);
+    assert_eq!(footer[0x28..], SAVED.to_le_bytes());
+
+    assert_eq!(clock_after(|gb| gb.load_rtc(&footer, LATER)), (0x03E1, 0xC2C3));
+    // an unknown save time leaves the clock as it was
+    let mut unstamped = footer;
+    unstamped[0x28..].fill(0);
+    assert_eq!(clock_after(|gb| gb.load_rtc(&unstamped, LATER)), (0x00E0, 0xC0C0));
+
+    let mut plain = GB::new();
+    plain.load_rom(&mbc1_rom(0x8000, 0x00)).unwrap();
+    assert_eq!(plain.save_rtc(SAVED), None);
+}
+
+#[test]
+fn clock_catches_up_with_bess_states() {
+    let mut gb = GB::new();
+    gb.load_rom(&rtc_rom()).unwrap();
+    let mut buf = vec![0; STATE_MAX_SIZE];
+    let len = gb.save_bess(&mut buf, SAVED).expect("BESS save failed");
+
+    assert_eq!(clock_after(|gb| gb.load_bess(&buf[..len], LATER).unwrap()), (0x03E1, 0xC2C3));
+}
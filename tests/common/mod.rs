@@ -0,0 +1,59 @@
+// shared by the test-ROM harnesses
+//
+// ROMs are looked up under $GB_TEST_ROMS (default tests/roms), one
+// directory per suite. they are not part of the tree, so the tests that
+// need them are #[ignore]d and fail if run without them:
+//
+//     GB_TEST_ROMS=/path/to/roms cargo test -- --ignored
+
+// each test crate only uses part of this
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use gb_rs::{CYCLES_PER_FRAME, CartHeader, GB, Registers};
+
+pub fn rom_path(suite: &str, rel: &str) -> PathBuf {
+    let base = env::var_os("GB_TEST_ROMS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms"));
+    base.join(suite).join(rel)
+}
+
+pub fn load_rom(suite: &str, rel: &str) -> Vec<u8> {
+    let path = rom_path(suite, rel);
+    fs::read(&path).unwrap_or_else(|e| panic!("{suite}/{rel}: {}: {e}", path.display()))
+}
+
+// fill in the header checksum of a hand assembled ROM so that it loads
+pub fn fix_header(rom: &mut [u8]) {
+    rom[0x14D] = rom[0x134..0x14D].iter().fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+    assert!(CartHeader::parse(rom).header_checksum_ok());
+}
+
+// a ROM running prog from 0150, with each handler at its interrupt vector
+pub fn program_rom(prog: &[u8], handlers: &[(usize, &[u8])]) -> Vec<u8> {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // nop; jp $0150
+    for &(vector, handler) in handlers {
+        rom[vector..vector + handler.len()].copy_from_slice(handler);
+    }
+    rom[0x150..0x150 + prog.len()].copy_from_slice(prog);
+    fix_header(&mut rom);
+    rom
+}
+
+// registers at the first LD B, B
+pub fn run_to_break(rom: &[u8], frames: usize) -> Registers {
+    let mut gb = GB::new();
+    gb.load_rom(rom).unwrap();
+    gb.set_ld_b_b_break(true);
+    for _ in 0..frames * CYCLES_PER_FRAME {
+        if gb.tick().is_break() {
+            return gb.registers();
+        }
+    }
+    panic!("breakpoint not reached");
+}
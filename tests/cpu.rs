@@ -0,0 +1,298 @@
+// CPU instructions checked with hand assembled ROMs that stop at LD B, B
+// see https://gbdev.io/pandocs/CPU_Instruction_Set.html
+
+mod common;
+
+use gb_rs::{CYCLES_PER_FRAME, GB, Registers};
+
+// registers once prog and a final LD B, B have run
+fn run(prog: &[u8]) -> Registers {
+    let prog = [prog, &[0x40]].concat(); // ld b, b
+    common::run_to_break(&common::program_rom(&prog, &[]), 1)
+}
+
+// A and F after each program
+fn check_af(cases: &[(&[u8], u16)]) {
+    for &(prog, af) in cases {
+        let regs = run(prog);
+        assert_eq!(regs.af, af, "{prog:02X?}: {regs:04X?}");
+    }
+}
+
+#[test]
+fn half_carry_takes_carry_in() {
+    #[rustfmt::skip]
+    check_af(&[
+        // scf; ld a, $0F; adc a, $00
+        (&[0x37, 0x3E, 0x0F, 0xCE, 0x00], 0x1020),
+        // scf; ld a, $10; sbc a, $0F
+        (&[0x37, 0x3E, 0x10, 0xDE, 0x0F], 0x00E0),
+    ]);
+}
+
+#[test]
+fn half_carry_out_of_bit_3_and_11() {
+    #[rustfmt::skip]
+    check_af(&[
+        // ld a, $08; add a, $08
+        (&[0x3E, 0x08, 0xC6, 0x08], 0x1020),
+        // ld a, $0F; or a; inc a
+        (&[0x3E, 0x0F, 0xB7, 0x3C], 0x1020),
+        // ld hl, $0FFF; ld bc, $0001; xor a; add hl, bc: Z is kept
+        (&[0x21, 0xFF, 0x0F, 0x01, 0x01, 0x00, 0xAF, 0x09], 0x00A0),
+    ]);
+}
+
+#[test]
+fn inc_dec_keep_carry() {
+    #[rustfmt::skip]
+    check_af(&[
+        // scf; ld a, $FF; inc a
+        (&[0x37, 0x3E, 0xFF, 0x3C], 0x00B0),
+        // scf; ld a, $01; dec a
+        (&[0x37, 0x3E, 0x01, 0x3D], 0x00D0),
+    ]);
+}
+
+#[test]
+fn accumulator_ops_flags() {
+    #[rustfmt::skip]
+    check_af(&[
+        // xor a; rlca: RLCA and friends always clear Z
+        (&[0xAF, 0x07], 0x0000),
+        // xor a; scf; ccf: Z is kept
+        (&[0xAF, 0x37, 0x3F], 0x0080),
+        // xor a; scf; cpl: so are Z and C
+        (&[0xAF, 0x37, 0x2F], 0xFFF0),
+        // ld bc, $12FF; push bc; pop af: the low nibble of F is always 0
+        (&[0x01, 0xFF, 0x12, 0xC5, 0xF1], 0x12F0),
+    ]);
+}
+
+#[test]
+fn shifts_and_swap() {
+    // xor a; ld b, $80; sla b: the carry comes out of bit 7
+    let regs = run(&[0xAF, 0x06, 0x80, 0xCB, 0x20]);
+    assert_eq!((regs.af, regs.bc >> 8), (0x0090, 0x00), "{regs:04X?}");
+
+    // ld a, $AA; ld b, $F1; swap b: only B changes
+    let regs = run(&[0x3E, 0xAA, 0x06, 0xF1, 0xCB, 0x30]);
+    assert_eq!((regs.af, regs.bc >> 8), (0xAA00, 0x1F), "{regs:04X?}");
+}
+
+#[test]
+fn sp_plus_signed_offset() {
+    // the offset is signed, the flags come from adding it unsigned to the
+    // low byte of SP
+    let cases = [
+        (0x00FF, 0x01, 0x0100, 0x30),
+        (0x1001, 0xFF, 0x1000, 0x30),
+        (0x1001, 0xFE, 0x0FFF, 0x00),
+    ];
+    for (sp, e, sum, f) in cases {
+        let [lo, hi] = u16::to_le_bytes(sp);
+        // ld sp, sp; ld hl, sp + e
+        let regs = run(&[0x31, lo, hi, 0xF8, e]);
+        assert_eq!((regs.hl, regs.af & 0xFF), (sum, f), "ld hl, sp + {}: {regs:04X?}", e as i8);
+        // ld sp, sp; add sp, e
+        let regs = run(&[0x31, lo, hi, 0xE8, e]);
+        assert_eq!((regs.sp, regs.af & 0xFF), (sum, f), "add sp, {}: {regs:04X?}", e as i8);
+    }
+}
+
+// M-cycles taken by instr, run between two LD B, B after setup; jumps
+// find the second one at $0200, RST $08 and the timer interrupt at their
+// vectors
+fn cycles(setup: &[u8], instr: &[u8]) -> usize {
+    let prog = [setup, &[0x40], instr, &[0x40]].concat(); // ld b, b
+    let mut rom = common::program_rom(&prog, &[(0x08, &[0x40]), (0x50, &[0x40])]);
+    rom[0x200] = 0x40;
+    common::fix_header(&mut rom);
+
+    let mut gb = GB::new();
+    gb.load_rom(&rom).unwrap();
+    gb.set_ld_b_b_break(true);
+    let mut breaks = Vec::new();
+    for cycle in 0..1000 {
+        if gb.tick().is_break() {
+            breaks.push(cycle);
+            if breaks.len() == 2 {
+                // less the LD B, B itself
+                return breaks[1] - breaks[0] - 1;
+            }
+        }
+    }
+    panic!("{prog:02X?}: breakpoints at {breaks:?}");
+}
+
+#[test]
+fn instruction_timing() {
+    // xor a sets Z for the conditions, HL and the stack point at $0200
+    let z: &[u8] = &[0xAF];
+    let hl: &[u8] = &[0x21, 0x00, 0x02];
+    let stack: &[u8] = &[0xAF, 0x21, 0x00, 0x02, 0xE5];
+    #[rustfmt::skip]
+    let cases: [(&str, &[u8], &[u8], usize); 14] = [
+        ("nop",           &[], &[0x00],             1),
+        ("ld sp, hl",     &[], &[0xF9],             2),
+        ("inc bc",        &[], &[0x03],             2),
+        ("dec bc",        &[], &[0x0B],             2),
+        ("add hl, bc",    &[], &[0x09],             2),
+        ("ld hl, sp + 1", &[], &[0xF8, 0x01],       3),
+        ("add sp, 1",     &[], &[0xE8, 0x01],       4),
+        ("jp nz, $0200",  z,   &[0xC2, 0x00, 0x02], 3),
+        ("jp z, $0200",   z,   &[0xCA, 0x00, 0x02], 4),
+        ("jr z, 0",       z,   &[0x28, 0x00],       3),
+        ("jp hl",         hl,  &[0xE9],             1),
+        ("rst $08",       &[], &[0xCF],             4),
+        ("ret nz",        z,   &[0xC0],             2),
+        ("ret z",         stack, &[0xC8],           5),
+    ];
+    for (name, setup, instr, expected) in cases {
+        assert_eq!(cycles(setup, instr), expected, "{name}");
+    }
+}
+
+#[test]
+fn rst_pushes_return_address() {
+    // rst $08, which pops the return address into DE
+    let rom = common::program_rom(&[0xCF], &[(0x08, &[0xD1, 0x40])]); // pop de; ld b, b
+    let regs = common::run_to_break(&rom, 1);
+    assert_eq!((regs.de, regs.sp, regs.pc), (0x0151, 0xFFFE, 0x000A), "{regs:04X?}");
+}
+
+const IF: u8 = 0x0F;
+const TIMA: u8 = 0x05;
+const TAC: u8 = 0x07;
+const IE: u8 = 0xFF;
+
+#[test]
+fn ei_delay_and_dispatch() {
+    // with the timer interrupt pending, EI lets one more instruction run,
+    // then dispatch takes 5 M-cycles to the vector
+    #[rustfmt::skip]
+    let setup = [
+        0x3E, 0x04, // ld a, $04
+        0xE0, IE,   // ldh [IE], a
+        0xE0, IF,   // ldh [IF], a
+    ];
+    assert_eq!(cycles(&setup, &[0xFB, 0x00]), 1 + 1 + 5); // ei; nop
+}
+
+#[test]
+fn dispatch_clears_ime() {
+    // the handler requests its own interrupt again, which waits for RETI
+    #[rustfmt::skip]
+    let prog = [
+        0x16, 0x00, // ld d, 0
+        0x3E, 0x04, // ld a, $04
+        0xE0, IE,   // ldh [IE], a
+        0xE0, IF,   // ldh [IF], a
+        0xFB,       // ei
+        0x18, 0xFE, // jr @
+    ];
+    #[rustfmt::skip]
+    let handler = [
+        0x14,       // inc d
+        0x3E, 0x04, // ld a, $04
+        0xE0, IF,   // ldh [IF], a
+        0x00,       // nop
+        0x40,       // ld b, b
+    ];
+    let regs = common::run_to_break(&common::program_rom(&prog, &[(0x50, &handler)]), 1);
+    assert_eq!(regs.de >> 8, 1, "{regs:04X?}");
+}
+
+#[test]
+fn halt_wakes_without_ime() {
+    // an interrupt becoming pending ends HALT even with IME cleared, but
+    // isn't dispatched
+    #[rustfmt::skip]
+    let prog = [
+        0x3E, 0x04, // ld a, $04
+        0xE0, IE,   // ldh [IE], a
+        0x3E, 0x05, // ld a, $05
+        0xE0, TAC,  // ldh [TAC], a
+        0x76,       // halt
+        0x40,       // ld b, b
+    ];
+    let rom = common::program_rom(&prog, &[(0x50, &[0x14, 0x40])]); // inc d; ld b, b
+    let regs = common::run_to_break(&rom, 1);
+    assert_eq!((regs.pc, regs.de >> 8), (0x015A, 0), "{regs:04X?}");
+}
+
+#[test]
+fn if_flags_ignore_ie() {
+    // IF records requests whether IE enables them or not, and its unused
+    // bits read 1
+    #[rustfmt::skip]
+    let prog = [
+        0xAF,       // xor a
+        0xE0, IE,   // ldh [IE], a
+        0xE0, IF,   // ldh [IF], a
+        0xF0, IF,   // ldh a, [IF]
+        0x5F,       // ld e, a
+        0x3E, 0xFF, // ld a, $FF
+        0xE0, TIMA, // ldh [TIMA], a
+        0x3E, 0x05, // ld a, $05
+        0xE0, TAC,  // ldh [TAC], a
+        0x0E, 0x08, // ld c, 8
+        0x0D,       // wait: dec c
+        0x20, 0xFD, // jr nz, wait
+        0xF0, IF,   // ldh a, [IF]
+    ];
+    let regs = run(&prog);
+    assert_eq!((regs.de & 0xFF, regs.af >> 8), (0xE0, 0xE4), "{regs:04X?}");
+}
+
+#[test]
+fn ie_keeps_all_bits() {
+    // unlike IF, IE reads back every bit written
+    #[rustfmt::skip]
+    let prog = [
+        0x3E, 0xE0, // ld a, $E0
+        0xE0, IE,   // ldh [IE], a
+        0xF0, IE,   // ldh a, [IE]
+    ];
+    let regs = run(&prog);
+    assert_eq!(regs.af >> 8, 0xE0, "{regs:04X?}");
+}
+
+const P1: u8 = 0x00;
+const DIV: u8 = 0x04;
+
+#[test]
+fn stop_freezes_div_and_blanks_lcd() {
+    // STOP after the logo is up, then DIV once a button ends it
+    #[rustfmt::skip]
+    let prog = [
+        0x3E, 0x10,       // ld a, $10
+        0xE0, P1,         // ldh [P1], a
+        0x01, 0x00, 0x18, // ld bc, $1800
+        0x0B,             // wait: dec bc
+        0x78,             // ld a, b
+        0xB1,             // or c
+        0x20, 0xFB,       // jr nz, wait
+        0x10, 0x00,       // stop
+        0xF0, DIV,        // ldh a, [DIV]
+        0x40,             // ld b, b
+    ];
+    let mut gb = GB::new();
+    gb.load_rom(&common::program_rom(&prog, &[])).unwrap();
+    gb.set_ld_b_b_break(true);
+    let white = |gb: &GB| gb.frame_buffer().iter().all(|&b| b == 0xFF);
+
+    for _ in 0..2 * CYCLES_PER_FRAME {
+        assert!(gb.tick().is_continue());
+    }
+    assert!(!white(&gb), "logo not drawn");
+    for _ in 0..3 * CYCLES_PER_FRAME {
+        assert!(gb.tick().is_continue());
+    }
+    assert!(white(&gb), "LCD not blank");
+
+    // A on the button line
+    gb.set_buttons(0xEF);
+    assert!((0..100).any(|_| gb.tick().is_break()), "still stopped");
+    assert_eq!(gb.registers().af >> 8, 0x00);
+}
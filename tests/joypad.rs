@@ -0,0 +1,45 @@
+// button input checked with hand assembled ROMs that stop at LD B, B
+
+mod common;
+
+use gb_rs::{CYCLES_PER_FRAME, GB};
+
+const P1: u8 = 0x00;
+const IE: u8 = 0xFF;
+
+// P1 in A from the joypad interrupt handler, with only the lines in
+// select low and the buttons of state pressed after a frame
+fn p1_on_press(select: u8, state: u8) -> Option<u8> {
+    #[rustfmt::skip]
+    let prog = [
+        0x3E, select, // ld a, select
+        0xE0, P1,     // ldh [P1], a
+        0x3E, 0x10,   // ld a, $10
+        0xE0, IE,     // ldh [IE], a
+        0xFB,         // ei
+        0x76,         // halt
+        0x18, 0xFD,   // jr @-1
+    ];
+    let handler = [0xF0, P1, 0x40]; // ldh a, [P1]; ld b, b
+    let mut gb = GB::new();
+    gb.load_rom(&common::program_rom(&prog, &[(0x60, &handler)])).unwrap();
+    gb.set_ld_b_b_break(true);
+
+    for _ in 0..CYCLES_PER_FRAME {
+        let _ = gb.tick();
+    }
+    gb.set_buttons(state);
+    (0..CYCLES_PER_FRAME)
+        .any(|_| gb.tick().is_break())
+        .then(|| (gb.registers().af >> 8) as u8)
+}
+
+#[test]
+fn buttons_reach_p1() {
+    // right on the d-pad line
+    assert_eq!(p1_on_press(0x20, 0xFE), Some(0xEE));
+    // start on the button line
+    assert_eq!(p1_on_press(0x10, 0x7F), Some(0xD7));
+    // start while only the d-pad is selected goes unnoticed
+    assert_eq!(p1_on_press(0x20, 0x7F), None);
+}
@@ -0,0 +1,174 @@
+// Mooneye GB acceptance tests, e.g. tests/roms/mooneye/acceptance/div_timing.gb
+//
+// a test ends at LD B, B and passes if B, C, D, E, H, L hold the first
+// Fibonacci numbers; failures leave $42 in all of them instead.
+
+mod common;
+
+use std::panic::{self, AssertUnwindSafe};
+
+use gb_rs::{CYCLES_PER_FRAME, GB, Model, Registers};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Pass,
+    Fail,
+    // no run with the ROM around has been recorded yet
+    Unknown,
+}
+
+use Outcome::*;
+
+// outcome of every ROM as last observed; any other outcome fails the
+// suite, a pass as much as a regression, so that the table keeps up.
+// Unknown entries are only reported, until a run fills them in
+#[rustfmt::skip]
+const RESULTS: &[(&str, Outcome)] = &[
+    ("acceptance/add_sp_e_timing.gb",                       Unknown),
+    ("acceptance/boot_div-dmgABCmgb.gb",                    Unknown),
+    ("acceptance/boot_hwio-dmgABCmgb.gb",                   Unknown),
+    ("acceptance/boot_regs-dmgABC.gb",                      Unknown),
+    ("acceptance/call_cc_timing.gb",                        Unknown),
+    ("acceptance/call_cc_timing2.gb",                       Unknown),
+    ("acceptance/call_timing.gb",                           Unknown),
+    ("acceptance/call_timing2.gb",                          Unknown),
+    ("acceptance/di_timing-GS.gb",                          Unknown),
+    ("acceptance/div_timing.gb",                            Unknown),
+    ("acceptance/ei_sequence.gb",                           Unknown),
+    ("acceptance/ei_timing.gb",                             Unknown),
+    ("acceptance/halt_ime0_ei.gb",                          Unknown),
+    ("acceptance/halt_ime0_nointr_timing.gb",               Unknown),
+    ("acceptance/halt_ime1_timing.gb",                      Unknown),
+    ("acceptance/halt_ime1_timing2-GS.gb",                  Unknown),
+    ("acceptance/if_ie_registers.gb",                       Unknown),
+    ("acceptance/intr_timing.gb",                           Unknown),
+    ("acceptance/jp_cc_timing.gb",                          Unknown),
+    ("acceptance/jp_timing.gb",                             Unknown),
+    ("acceptance/ld_hl_sp_e_timing.gb",                     Unknown),
+    ("acceptance/oam_dma_restart.gb",                       Unknown),
+    ("acceptance/oam_dma_start.gb",                         Unknown),
+    ("acceptance/oam_dma_timing.gb",                        Unknown),
+    ("acceptance/pop_timing.gb",                            Unknown),
+    ("acceptance/push_timing.gb",                           Unknown),
+    ("acceptance/rapid_di_ei.gb",                           Unknown),
+    ("acceptance/ret_cc_timing.gb",                         Unknown),
+    ("acceptance/ret_timing.gb",                            Unknown),
+    ("acceptance/reti_intr_timing.gb",                      Unknown),
+    ("acceptance/reti_timing.gb",                           Unknown),
+    ("acceptance/rst_timing.gb",                            Unknown),
+    ("acceptance/bits/mem_oam.gb",                          Unknown),
+    ("acceptance/bits/reg_f.gb",                            Unknown),
+    ("acceptance/bits/unused_hwio-GS.gb",                   Unknown),
+    ("acceptance/instr/daa.gb",                             Unknown),
+    ("acceptance/interrupts/ie_push.gb",                    Unknown),
+    ("acceptance/oam_dma/basic.gb",                         Unknown),
+    ("acceptance/oam_dma/reg_read.gb",                      Unknown),
+    ("acceptance/oam_dma/sources-GS.gb",                    Unknown),
+    ("acceptance/ppu/hblank_ly_scx_timing-GS.gb",           Unknown),
+    ("acceptance/ppu/intr_1_2_timing-GS.gb",                Unknown),
+    ("acceptance/ppu/intr_2_0_timing.gb",                   Unknown),
+    ("acceptance/ppu/intr_2_mode0_timing.gb",               Unknown),
+    ("acceptance/ppu/intr_2_mode0_timing_sprites.gb",       Unknown),
+    ("acceptance/ppu/intr_2_mode3_timing.gb",               Unknown),
+    ("acceptance/ppu/intr_2_oam_ok_timing.gb",              Unknown),
+    ("acceptance/ppu/lcdon_timing-GS.gb",                   Unknown),
+    ("acceptance/ppu/lcdon_write_timing-GS.gb",             Unknown),
+    ("acceptance/ppu/stat_irq_blocking.gb",                 Unknown),
+    ("acceptance/ppu/stat_lyc_onoff.gb",                    Unknown),
+    ("acceptance/ppu/vblank_stat_intr-GS.gb",               Unknown),
+    ("acceptance/serial/boot_sclk_align-dmgABCmgb.gb",      Unknown),
+    ("acceptance/timer/div_write.gb",                       Unknown),
+    ("acceptance/timer/rapid_toggle.gb",                    Unknown),
+    ("acceptance/timer/tim00.gb",                           Unknown),
+    ("acceptance/timer/tim00_div_trigger.gb",               Unknown),
+    ("acceptance/timer/tim01.gb",                           Unknown),
+    ("acceptance/timer/tim01_div_trigger.gb",               Unknown),
+    ("acceptance/timer/tim10.gb",                           Unknown),
+    ("acceptance/timer/tim10_div_trigger.gb",               Unknown),
+    ("acceptance/timer/tim11.gb",                           Unknown),
+    ("acceptance/timer/tim11_div_trigger.gb",               Unknown),
+    ("acceptance/timer/tima_reload.gb",                     Unknown),
+    ("acceptance/timer/tima_write_reloading.gb",            Unknown),
+    ("acceptance/timer/tma_write_reloading.gb",             Unknown),
+];
+
+const MAX_FRAMES: usize = 600;
+
+fn passed(regs: &Registers) -> bool {
+    regs.bc == 0x0305 && regs.de == 0x080D && regs.hl == 0x1522
+}
+
+// registers at the breakpoint, or None on timeout
+fn run(rom: &[u8]) -> Option<Registers> {
+    let mut gb = GB::new();
+    gb.load_rom(rom).expect("failed to load ROM");
+    gb.set_ld_b_b_break(true);
+
+    for _ in 0..MAX_FRAMES * CYCLES_PER_FRAME {
+        if gb.tick().is_break() {
+            return Some(gb.registers());
+        }
+    }
+    None
+}
+
+#[test]
+#[ignore = "needs the Mooneye ROMs, see tests/common"]
+fn acceptance() {
+    let mut changed = Vec::new();
+
+    for &(rel, expected) in RESULTS {
+        let rom = common::load_rom("mooneye", rel);
+
+        // unimplemented hardware panics, which only counts as a failure here
+        let result = panic::catch_unwind(AssertUnwindSafe(|| run(&rom)));
+        let (outcome, detail) = match result {
+            Ok(Some(regs)) if passed(&regs) => (Pass, String::new()),
+            Ok(Some(regs)) => (Fail, format!("{regs:04X?}")),
+            Ok(None) => (Fail, String::from("timeout")),
+            Err(_) => (Fail, String::from("panicked")),
+        };
+
+        eprintln!("{outcome:?}: {rel} {detail}");
+        if expected != Unknown && outcome != expected {
+            changed.push(format!("{rel}: {expected:?} in RESULTS, now {outcome:?}"));
+        }
+    }
+
+    assert!(changed.is_empty(), "RESULTS out of date: {changed:#?}");
+}
+
+// a hand assembled ROM that reports success, so the breakpoint hook is
+// exercised even without any ROMs around
+#[test]
+fn harness_stops_at_ld_b_b() {
+    let mut rom = vec![0u8; 0x8000];
+    #[rustfmt::skip]
+    let prog = [
+        0x01, 0x05, 0x03, // ld bc, $0305
+        0x11, 0x0D, 0x08, // ld de, $080D
+        0x21, 0x22, 0x15, // ld hl, $1522
+        0x40,             // ld b, b
+        0x18, 0xFE,       // jr @
+    ];
+    rom[0x100..0x100 + prog.len()].copy_from_slice(&prog);
+    common::fix_header(&mut rom);
+
+    let regs = run(&rom).expect("breakpoint not reached");
+    assert!(passed(&regs), "{regs:04X?}");
+    assert_eq!(regs.pc, 0x010A);
+}
+
+#[test]
+fn break_survives_loading_a_rom() {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x100..0x103].copy_from_slice(&[0x40, 0x18, 0xFE]); // ld b, b; jr @
+    common::fix_header(&mut rom);
+
+    let mut gb = GB::new();
+    gb.set_ld_b_b_break(true);
+    gb.load_rom(&rom).unwrap();
+    gb.set_model(Model::Dmg);
+    assert!((0..CYCLES_PER_FRAME).any(|_| gb.tick().is_break()), "breakpoint lost");
+    assert_eq!(gb.registers().pc, 0x0101);
+}
@@ -0,0 +1,585 @@
+// PPU behaviour checked with hand assembled ROMs, which either stop at
+// LD B, B from an interrupt handler or leave a picture to look at
+
+mod common;
+
+use gb_rs::{CYCLES_PER_FRAME, GB, Renderer};
+
+const LY: u8 = 0x44;
+const STAT: u8 = 0x41;
+const LYC: u8 = 0x45;
+const IF: u8 = 0x0F;
+const IE: u8 = 0xFF;
+
+// writes every value in turn from 0150, then goes on with tail;
+// stat_handler is placed at the STAT interrupt vector
+fn assemble(writes: &[(u16, u8)], tail: &[u8], stat_handler: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // nop; jp $0150
+    rom[0x48..0x48 + stat_handler.len()].copy_from_slice(stat_handler);
+
+    let mut prog = Vec::new();
+    for &(addr, val) in writes {
+        let [lo, hi] = addr.to_le_bytes();
+        prog.extend_from_slice(&[0x3E, val, 0xEA, lo, hi]); // ld a, val; ld [addr], a
+    }
+    prog.extend_from_slice(tail);
+    rom[0x150..0x150 + prog.len()].copy_from_slice(&prog);
+    common::fix_header(&mut rom);
+    rom
+}
+
+// the writes, then waits for interrupts forever
+fn rom(writes: &[(u16, u8)], stat_handler: &[u8]) -> Vec<u8> {
+    #[rustfmt::skip]
+    let tail = [
+        0xFB,       // ei
+        0x76,       // halt
+        0x18, 0xFD, // jr @-1
+    ];
+    assemble(writes, &tail, stat_handler)
+}
+
+fn io(reg: u8) -> u16 {
+    0xFF00 | reg as u16
+}
+
+// the STAT interrupt with only the given conditions selected
+fn stat_rom(select: u8, lyc: u8, handler: &[u8]) -> Vec<u8> {
+    rom(&[(io(IF), 0x00), (io(IE), 0x02), (io(LYC), lyc), (io(STAT), select)], handler)
+}
+
+// LY in B and STAT in C
+const REPORT: [u8; 6] = [
+    0xF0, LY,   // ldh a, [LY]
+    0x47,       // ld b, a
+    0xF0, STAT, // ldh a, [STAT]
+    0x4F,       // ld c, a
+];
+
+#[test]
+fn lyc_interrupt() {
+    let handler = [&REPORT[..], &[0x40]].concat(); // ld b, b
+    let regs = common::run_to_break(&stat_rom(0x40, 0x42, &handler), 2);
+    let (ly, stat) = (regs.bc >> 8, regs.bc as u8);
+    assert_eq!(ly, 0x42);
+    // coincidence flag, still in OAM scan; the mode bits don't take writes
+    assert_eq!(stat, 0x80 | 0x40 | 0x04 | 2, "STAT {stat:02X}");
+}
+
+#[test]
+fn mode_interrupts() {
+    let handler = [&REPORT[..], &[0x40]].concat(); // ld b, b
+    for (select, mode) in [(0x08, 0), (0x10, 1), (0x20, 2)] {
+        let regs = common::run_to_break(&stat_rom(select, 0xFF, &handler), 2);
+        let stat = regs.bc as u8;
+        assert_eq!(stat & 0x03, mode, "select {select:02X}, STAT {stat:02X}");
+    }
+}
+
+#[test]
+fn stat_blocking() {
+    // with mode 0 and 2 both selected, HBlank keeps the line high into
+    // the next OAM scan, so mode 2 only interrupts coming out of VBlank;
+    // the handler counts interrupts in D and stops at the first mode 2 one
+    #[rustfmt::skip]
+    let handler = [
+        0x14,             // inc d
+        0xF0, STAT,       // ldh a, [STAT]
+        0xE6, 0x03,       // and $03
+        0xFE, 0x02,       // cp 2
+        0x20, 0x04,       // jr nz, .ret
+        0xF0, LY,         // ldh a, [LY]
+        0x5F,             // ld e, a
+        0x40,             // ld b, b
+        0xD9,             // .ret: reti
+    ];
+    let regs = common::run_to_break(&stat_rom(0x28, 0xFF, &handler), 3);
+    let (count, ly) = (regs.de >> 8, regs.de as u8);
+    assert_eq!(ly, 0);
+    assert!(count > 100, "{count} interrupts before mode 2");
+}
+
+const LCDC: u8 = 0x40;
+const BGP: u8 = 0x47;
+const OBP0: u8 = 0x48;
+const OBP1: u8 = 0x49;
+
+// shade 0 (white) to 3 (black) of a pixel
+fn shade(gb: &GB, x: usize, y: usize) -> u8 {
+    let px = gb.frame_buffer()[(y * 160 + x) * 4];
+    3 - px / 0x55
+}
+
+fn run_frames(rom: &[u8], frames: usize) -> Box<GB> {
+    let mut gb = GB::new();
+    gb.load_rom(rom).unwrap();
+    for _ in 0..frames * CYCLES_PER_FRAME {
+        let _ = gb.tick();
+    }
+    gb
+}
+
+// with the LCD off, tile 1 solid color 3, tile 2 solid color 1, tile 3
+// color 3 on its right half only
+fn tile_writes() -> Vec<(u16, u8)> {
+    let mut writes = vec![(io(LCDC), 0x00)];
+    for row in 0..8 {
+        writes.extend([(0x8010 + row * 2, 0xFF), (0x8011 + row * 2, 0xFF)]);
+        writes.extend([(0x8020 + row * 2, 0xFF), (0x8021 + row * 2, 0x00)]);
+        writes.extend([(0x8030 + row * 2, 0x0F), (0x8031 + row * 2, 0x0F)]);
+    }
+    writes
+}
+
+// objects on screen row 16
+fn obj_writes(lcdc: u8, bgp: u8) -> Vec<(u16, u8)> {
+    let mut writes = tile_writes();
+    // BG color 1 under screen columns 64..71 of the object row
+    writes.push((0x9848, 0x02));
+
+    #[rustfmt::skip]
+    let oam = [
+        // y, x, tile, attr
+        (32, 20, 3, 0x00), // right half over the left of the next one
+        (32, 16, 2, 0x00), // lower X, drawn on top
+        (32, 40, 3, 0x00), // transparent half shows the next one
+        (32, 42, 2, 0x10), // OBP1
+        (32, 72, 1, 0x80), // behind BG color 1
+        (32, 80, 1, 0x80), // behind BG color 0, so still drawn
+    ];
+    for (i, (y, x, tile, attr)) in oam.into_iter().enumerate() {
+        let addr = 0xFE00 + 4 * i as u16;
+        writes.extend([(addr, y), (addr + 1, x), (addr + 2, tile), (addr + 3, attr)]);
+    }
+    writes.extend([(io(BGP), bgp), (io(OBP0), 0xE4), (io(OBP1), 0x1B), (io(LCDC), lcdc)]);
+    writes
+}
+
+#[test]
+fn object_priority() {
+    let gb = run_frames(&rom(&obj_writes(0x93, 0xE4), &[]), 2);
+    let row = |y: usize, from: usize, to: usize| (from..to).map(|x| shade(&gb, x, y)).collect::<Vec<_>>();
+
+    assert_eq!(row(16, 6, 22), [0, 0, 1, 1, 1, 1, 1, 1, 1, 1, 3, 3, 3, 3, 0, 0]);
+    // OBP1 inverts color 1 into shade 2
+    assert_eq!(row(16, 32, 42), [0, 0, 2, 2, 3, 3, 3, 3, 2, 2]);
+    assert_eq!(row(16, 64, 80), [1, 1, 1, 1, 1, 1, 1, 1, 3, 3, 3, 3, 3, 3, 3, 3]);
+    // just above the objects
+    assert_eq!(row(15, 0, 160), vec![0; 160]);
+}
+
+#[test]
+fn bg_disabled_leaves_objects_on_white() {
+    // BGP would make the background black if it was drawn at all
+    let gb = run_frames(&rom(&obj_writes(0x92, 0xFF), &[]), 2);
+    assert_eq!(shade(&gb, 0, 0), 0);
+    assert_eq!(shade(&gb, 0, 16), 0);
+    // BG-over-OBJ doesn't apply without a BG
+    assert_eq!(shade(&gb, 64, 16), 3);
+    assert_eq!(shade(&gb, 10, 16), 1);
+}
+
+const WY: u8 = 0x4A;
+const WX: u8 = 0x4B;
+
+// window from the 9C00 map, each of its rows filled with one tile, over a
+// white BG
+fn window_writes(wx: u8, wy: u8, rows: &[u8]) -> Vec<(u16, u8)> {
+    let mut writes = tile_writes();
+    for (row, &tile) in rows.iter().enumerate() {
+        for col in 0..21 {
+            writes.push((0x9C00 + row as u16 * 32 + col, tile));
+        }
+    }
+    writes.extend([(io(WX), wx), (io(WY), wy), (io(BGP), 0xE4), (io(LCDC), 0xF1)]);
+    writes
+}
+
+fn column(gb: &GB, x: usize, from: usize, to: usize) -> Vec<u8> {
+    (from..to).map(|y| shade(gb, x, y)).collect()
+}
+
+#[test]
+fn window_line_counter() {
+    // turns the window off at line 4, on at 12, off at 20 and on at 28;
+    // it picks up from the row it stopped at rather than from LY - WY
+    #[rustfmt::skip]
+    let handler = [
+        0xF0, LCDC, // ldh a, [LCDC]
+        0xEE, 0x20, // xor $20
+        0xE0, LCDC, // ldh [LCDC], a
+        0xF0, LYC,  // ldh a, [LYC]
+        0xC6, 0x08, // add 8
+        0xE6, 0x1F, // and $1F
+        0xE0, LYC,  // ldh [LYC], a
+        0xD9,       // reti
+    ];
+    let mut writes = window_writes(7, 0, &[1, 2]);
+    writes.extend([(io(IF), 0x00), (io(IE), 0x02), (io(LYC), 4), (io(STAT), 0x40)]);
+    let gb = run_frames(&rom(&writes, &handler), 2);
+
+    assert_eq!(column(&gb, 0, 0, 24), [[3; 4], [0; 4], [0; 4], [3; 4], [1; 4], [0; 4]].concat());
+}
+
+#[test]
+fn window_wy_latch() {
+    // WY moving past LY after matching it doesn't take the window away;
+    // it goes off screen at line 10 and back to 8 at line 150
+    #[rustfmt::skip]
+    let handler = [
+        0xF0, WY,   // ldh a, [WY]
+        0x2F,       // cpl
+        0xE0, WY,   // ldh [WY], a
+        0xF0, LYC,  // ldh a, [LYC]
+        0xEE, 0x9C, // xor 10 ^ 150
+        0xE0, LYC,  // ldh [LYC], a
+        0xD9,       // reti
+    ];
+    let mut writes = window_writes(7, 8, &[1, 2]);
+    writes.extend([(io(IF), 0x00), (io(IE), 0x02), (io(LYC), 10), (io(STAT), 0x40)]);
+    let gb = run_frames(&rom(&writes, &handler), 2);
+
+    assert_eq!(column(&gb, 0, 6, 18), [&[0; 2][..], &[3; 8], &[1; 2]].concat());
+}
+
+#[test]
+fn window_x_edges() {
+    let row = |gb: &GB| (0..12).map(|x| shade(gb, x, 0)).collect::<Vec<_>>();
+
+    // left part of the window is cut off below WX=7
+    let gb = run_frames(&rom(&window_writes(3, 0, &[3]), &[]), 2);
+    assert_eq!(row(&gb), [3, 3, 3, 3, 0, 0, 0, 0, 3, 3, 3, 3]);
+    let gb = run_frames(&rom(&window_writes(0, 0, &[3]), &[]), 2);
+    assert_eq!(row(&gb), [3, 0, 0, 0, 0, 3, 3, 3, 3, 0, 0, 0]);
+
+    // WX=166 shows nothing on the line it matches, all of the line after
+    let gb = run_frames(&rom(&window_writes(166, 0, &[1, 2]), &[]), 2);
+    assert_eq!(column(&gb, 0, 0, 10), [0, 3, 3, 3, 3, 3, 3, 3, 3, 1]);
+    assert_eq!(shade(&gb, 159, 0), 0);
+}
+
+#[test]
+fn vram_oam_locking() {
+    // polls STAT for each mode in turn: OAM is read during OAM scan into
+    // B, VRAM while drawing into D, and both in HBlank into E and C
+    #[rustfmt::skip]
+    let wait_mode = |mode: u8| [
+        0xF0, STAT, // .wait: ldh a, [STAT]
+        0xE6, 0x03, // and $03
+        0xFE, mode, // cp mode
+        0x20, 0xF8, // jr nz, .wait
+    ];
+    #[rustfmt::skip]
+    let code = [
+        &wait_mode(2)[..],
+        &[
+            0xFA, 0x00, 0xFE, // ld a, [$FE00]
+            0x47,             // ld b, a
+        ],
+        &wait_mode(3),
+        &[
+            0xFA, 0x00, 0x80, // ld a, [$8000]
+            0x57,             // ld d, a
+        ],
+        &wait_mode(0),
+        &[
+            0xFA, 0x00, 0xFE, // ld a, [$FE00]
+            0x5F,             // ld e, a
+            0xFA, 0x00, 0x80, // ld a, [$8000]
+            0x4F,             // ld c, a
+            0x40,             // ld b, b
+        ],
+    ]
+    .concat();
+    let writes = [(io(LCDC), 0x00), (0x8000, 0x5A), (0xFE00, 0xA5), (io(LCDC), 0x91)];
+    let regs = common::run_to_break(&assemble(&writes, &code, &[]), 2);
+    assert_eq!([regs.bc, regs.de], [0xFF5A, 0xFFA5]);
+}
+
+// turns the LCD off at line 100 over a black BG, with only the VBlank
+// interrupt enabled and its handler stopping at LD B, B
+#[test]
+fn lcd_off() {
+    #[rustfmt::skip]
+    let code = [
+        0xF0, LY,   // .wait: ldh a, [LY]
+        0xFE, 100,  // cp 100
+        0x20, 0xFA, // jr nz, .wait
+        0xAF,       // xor a
+        0xE0, LCDC, // ldh [LCDC], a
+        0xF0, LY,   // ldh a, [LY]
+        0x47,       // ld b, a
+        0xF0, STAT, // ldh a, [STAT]
+        0x4F,       // ld c, a
+        0xFB,       // ei
+        0x76,       // halt
+        0x18, 0xFD, // jr @-1
+    ];
+    let writes = [(io(BGP), 0xFF), (io(IF), 0x00), (io(IE), 0x01)];
+    let mut rom = assemble(&writes, &code, &[]);
+    rom[0x40] = 0x40; // ld b, b
+
+    let mut gb = GB::new();
+    gb.load_rom(&rom).unwrap();
+    gb.set_ld_b_b_break(true);
+    for _ in 0..3 * CYCLES_PER_FRAME {
+        assert!(gb.tick().is_continue(), "VBlank with the LCD off");
+    }
+
+    assert!(gb.lcd_off());
+    let regs = gb.registers();
+    assert_eq!(regs.bc >> 8, 0);
+    assert_eq!(regs.bc & 0x03, 0);
+    assert!(gb.frame_buffer().iter().all(|&b| b == 0xFF));
+}
+
+#[test]
+fn lcd_on_first_frame() {
+    // mode 0 instead of OAM scan right after turning the LCD back on, and
+    // the black BG only shows from the frame after
+    #[rustfmt::skip]
+    let code = [
+        0xAF,       // xor a
+        0xE0, LCDC, // ldh [LCDC], a
+        0x3E, 0x91, // ld a, $91
+        0xE0, LCDC, // ldh [LCDC], a
+        0xF0, STAT, // ldh a, [STAT]
+        0x4F,       // ld c, a
+        0xF0, LY,   // ldh a, [LY]
+        0x47,       // ld b, a
+        0x40,       // ld b, b
+        0x18, 0xFE, // jr @
+    ];
+    let mut gb = GB::new();
+    gb.load_rom(&assemble(&[(io(BGP), 0xFF)], &code, &[])).unwrap();
+    gb.set_ld_b_b_break(true);
+    while gb.tick().is_continue() {}
+    // LY=0 at line 0, LYC being 0 too
+    assert_eq!(gb.registers().bc, 0x0084);
+    assert!(!gb.lcd_off());
+
+    for _ in 0..CYCLES_PER_FRAME - 100 {
+        let _ = gb.tick();
+    }
+    assert_eq!(shade(&gb, 80, 143), 0);
+    for _ in 0..CYCLES_PER_FRAME {
+        let _ = gb.tick();
+    }
+    assert_eq!(shade(&gb, 80, 143), 3);
+}
+
+const SCX: u8 = 0x43;
+
+// M-cycles / 4 from the start of OAM scan on line 50 to HBlank, with ten
+// transparent objects on the line, obj_x apart from x 0; regs are written
+// before the LCD goes on
+fn mode3_count(lcdc: u8, obj_x: u8, regs: &[(u8, u8)], renderer: Renderer) -> u16 {
+    let mut writes = vec![(io(LCDC), 0x00)];
+    for i in 0..10 {
+        let addr = 0xFE00 + 4 * i;
+        writes.extend([(addr, 16 + 50), (addr + 1, 8 + obj_x * i as u8)]);
+    }
+    writes.extend(regs.iter().map(|&(reg, val)| (io(reg), val)));
+    writes.extend([(io(STAT), 0x08), (io(IE), 0x02), (io(LCDC), lcdc)]);
+    #[rustfmt::skip]
+    let code = [
+        0xF0, LY,   // .wait: ldh a, [LY]
+        0xFE, 50,   // cp 50
+        0x20, 0xFA, // jr nz, .wait
+        0xAF,       // xor a
+        0xE0, IF,   // ldh [IF], a
+        0xFB,       // ei
+        0x47,       // ld b, a
+        0x04,       // .count: inc b
+        0x18, 0xFD, // jr .count
+    ];
+    let mut rom = assemble(&writes, &code, &[0x40]); // ld b, b
+    rom[0x40] = 0xD9; // reti
+
+    let mut gb = GB::new();
+    gb.set_renderer(renderer);
+    gb.load_rom(&rom).unwrap();
+    gb.set_ld_b_b_break(true);
+    assert!((0..3 * CYCLES_PER_FRAME).any(|_| gb.tick().is_break()), "breakpoint not reached");
+    gb.registers().bc >> 8
+}
+
+#[test]
+fn objects_lengthen_mode3() {
+    // each object stalls the FIFO for 6 to 11 dots, 16 dots a count
+    let (none, ten) = (mode3_count(0x91, 8, &[], Renderer::Fifo), mode3_count(0x93, 8, &[], Renderer::Fifo));
+    assert!((60 / 16..=110 / 16 + 1).contains(&(ten - none)), "{none} counts without objects, {ten} with");
+}
+
+#[test]
+fn scanline_mode3_matches_fifo() {
+    // objects off the tile grid also wait for the BG fetcher, at most once
+    // per tile, and the window adds its own start part way along
+    let cases = [
+        (0x93, 8, &[][..]),
+        (0x93, 8, &[(SCX, 3)]),
+        (0x93, 3, &[(SCX, 6)]),
+        (0x93, 13, &[(SCX, 1)]),
+        (0xB3, 9, &[(WX, 47), (WY, 0)]),
+        (0xB3, 5, &[(SCX, 5), (WX, 4), (WY, 0)]),
+    ];
+    for (lcdc, obj_x, regs) in cases {
+        let fifo = mode3_count(lcdc, obj_x, regs, Renderer::Fifo);
+        let scanline = mode3_count(lcdc, obj_x, regs, Renderer::Scanline);
+        assert_eq!(scanline, fifo, "objects {obj_x} apart, {regs:02X?}");
+    }
+}
+
+#[test]
+fn bgp_mid_line() {
+    // BGP goes black part way through drawing line 50, and white again
+    // on the next line
+    #[rustfmt::skip]
+    let code = [
+        0xF0, LY,   // .top: ldh a, [LY]
+        0xFE, 50,   // cp 50
+        0x20, 0xFA, // jr nz, .top
+        0x3E, 7,    // ld a, 7
+        0x3D,       // .delay: dec a
+        0x20, 0xFD, // jr nz, .delay
+        0x3E, 0xFF, // ld a, $FF
+        0xE0, BGP,  // ldh [BGP], a
+        0xF0, LY,   // .wait: ldh a, [LY]
+        0xFE, 51,   // cp 51
+        0x20, 0xFA, // jr nz, .wait
+        0xAF,       // xor a
+        0xE0, BGP,  // ldh [BGP], a
+        0x18, 0xE6, // jr .top
+    ];
+    let gb = run_frames(&assemble(&[(io(BGP), 0x00)], &code, &[]), 3);
+    let row = |y: usize| (0..160).map(|x| shade(&gb, x, y)).collect::<Vec<_>>();
+
+    assert_eq!(row(49), vec![0; 160]);
+    assert_eq!(row(51), vec![0; 160]);
+    let change = row(50).iter().position(|&c| c == 3).unwrap();
+    assert!(change > 8 && change < 152, "black from {change}");
+    assert!(row(50)[change..].iter().all(|&c| c == 3));
+}
+
+#[test]
+fn scanline_renderer_matches_fifo() {
+    // the pictures above, none of which touch the registers mid-line
+    let roms = [
+        rom(&obj_writes(0x93, 0xE4), &[]),
+        rom(&obj_writes(0x92, 0xFF), &[]),
+        rom(&window_writes(3, 0, &[3]), &[]),
+        rom(&window_writes(0, 0, &[3]), &[]),
+        rom(&window_writes(30, 20, &[1, 2, 3]), &[]),
+        rom(&window_writes(166, 0, &[1, 2]), &[]),
+    ];
+    for (i, rom) in roms.iter().enumerate() {
+        let fifo = run_frames(rom, 2);
+        let mut gb = GB::new();
+        gb.set_renderer(Renderer::Scanline);
+        gb.load_rom(rom).unwrap();
+        for _ in 0..2 * CYCLES_PER_FRAME {
+            let _ = gb.tick();
+        }
+        assert_eq!(gb.renderer(), Renderer::Scanline);
+        assert!(gb.frame_buffer() == fifo.frame_buffer(), "picture {i} differs");
+    }
+}
+
+// with the LCD off and the writes done, runs code copied to HRAM, the only
+// memory left to the CPU during OAM DMA
+fn hram_rom(writes: &[(u16, u8)], code: &[u8]) -> Vec<u8> {
+    let mut writes = [&[(io(LCDC), 0x00)], writes].concat();
+    writes.extend(code.iter().enumerate().map(|(i, &b)| (0xFF80 + i as u16, b)));
+
+    assemble(&writes, &[0xC3, 0x80, 0xFF], &[]) // jp $FF80
+}
+
+const DMA: u8 = 0x46;
+
+// marks both ends of pages C0 and C1
+const DMA_SOURCES: [(u16, u8); 4] = [(0xC000, 0x12), (0xC09F, 0x34), (0xC100, 0x56), (0xC19F, 0x78)];
+
+// the first and last byte of OAM in B and C
+const OAM_REPORT: [u8; 9] = [
+    0xFA, 0x00, 0xFE, // ld a, [$FE00]
+    0x47,             // ld b, a
+    0xFA, 0x9F, 0xFE, // ld a, [$FE9F]
+    0x4F,             // ld c, a
+    0x40,             // ld b, b
+];
+
+#[test]
+fn oam_dma() {
+    #[rustfmt::skip]
+    let code = [
+        &[
+            0x3E, 0xC0,       // ld a, $C0
+            0xE0, DMA,        // ldh [DMA], a
+            0xFA, 0x00, 0xFE, // ld a, [$FE00]
+            0x57,             // ld d, a
+            0xF0, DMA,        // ldh a, [DMA]
+            0x5F,             // ld e, a
+            0x3E, 40,         // ld a, 40
+            0x3D,             // .wait: dec a
+            0x20, 0xFD,       // jr nz, .wait
+        ][..],
+        &OAM_REPORT,
+    ]
+    .concat();
+    let regs = common::run_to_break(&hram_rom(&DMA_SOURCES, &code), 2);
+    assert_eq!(regs.bc, 0x1234);
+    // OAM is out of reach while the transfer runs, FF46 isn't
+    assert_eq!(regs.de, 0xFFC0);
+}
+
+#[test]
+fn oam_dma_restart() {
+    // the second transfer starts over from the top of its own page
+    #[rustfmt::skip]
+    let code = [
+        &[
+            0x3E, 0xC0, // ld a, $C0
+            0xE0, DMA,  // ldh [DMA], a
+            0x3E, 20,   // ld a, 20
+            0x3D,       // .wait: dec a
+            0x20, 0xFD, // jr nz, .wait
+            0x3E, 0xC1, // ld a, $C1
+            0xE0, DMA,  // ldh [DMA], a
+            0x3E, 40,   // ld a, 40
+            0x3D,       // .wait: dec a
+            0x20, 0xFD, // jr nz, .wait
+        ][..],
+        &OAM_REPORT,
+    ]
+    .concat();
+    let regs = common::run_to_break(&hram_rom(&DMA_SOURCES, &code), 2);
+    assert_eq!(regs.bc, 0x5678);
+}
+
+// Matt Currie's dmg-acid2, tests/roms/dmg-acid2/dmg-acid2.gb, next to its
+// reference image converted to a binary PPM, e.g. with
+// `convert reference-dmg.png reference-dmg.ppm`
+#[test]
+#[ignore = "needs dmg-acid2 and its reference image, see tests/common"]
+fn dmg_acid2() {
+    let rom = common::load_rom("dmg-acid2", "dmg-acid2.gb");
+    let reference = common::load_rom("dmg-acid2", "reference-dmg.ppm");
+
+    let mut gb = GB::new();
+    gb.load_rom(&rom).unwrap();
+    gb.set_ld_b_b_break(true);
+    // it signals being done with LD B, B, the frame after that is complete
+    let done = (0..60 * CYCLES_PER_FRAME).any(|_| gb.tick().is_break());
+    assert!(done, "LD B, B not reached");
+    gb.set_ld_b_b_break(false);
+    for _ in 0..2 * CYCLES_PER_FRAME {
+        let _ = gb.tick();
+    }
+
+    let pixels = &reference[reference.len() - 160 * 144 * 3..];
+    let mismatches = (0..160 * 144)
+        .filter(|&i| pixels[i * 3] != gb.frame_buffer()[i * 4])
+        .count();
+    assert_eq!(mismatches, 0, "pixels differ from the reference");
+}
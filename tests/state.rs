@@ -0,0 +1,256 @@
+// save states: restoring a snapshot must replay the machine exactly
+
+mod common;
+
+use gb_rs::{CYCLES_PER_FRAME, GB, Rewind, STATE_MAX_SIZE, StateError};
+
+// MBC1 with battery RAM, busy writing to the BG map, cart RAM and the
+// timer so that every part of the machine changes
+fn busy_rom(title: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0u8; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]); // nop; jp $0150
+    rom[0x134..0x134 + title.len()].copy_from_slice(title);
+    rom[0x147] = 0x03; // MBC1+RAM+BATTERY
+    rom[0x149] = 0x02; // 8 KiB
+    #[rustfmt::skip]
+    let prog = [
+        0x3E, 0x0A,       // ld a, $0A
+        0xEA, 0x00, 0x00, // ld [$0000], a
+        0x3E, 0x05,       // ld a, $05
+        0xE0, 0x07,       // ldh [TAC], a
+        0x21, 0x00, 0x98, // loop: ld hl, $9800
+        0x04,             // inc b
+        0x78,             // ld a, b
+        0x77,             // fill: ld [hl], a
+        0x23,             // inc hl
+        0xEA, 0x00, 0xA0, // ld [$A000], a
+        0xF0, 0x05,       // ldh a, [TIMA]
+        0x7C,             // ld a, h
+        0xFE, 0x9C,       // cp $9C
+        0x20, 0xF5,       // jr nz, fill
+        0x18, 0xEE,       // jr loop
+    ];
+    rom[0x150..0x150 + prog.len()].copy_from_slice(&prog);
+    common::fix_header(&mut rom);
+    rom
+}
+
+fn run(gb: &mut GB, cycles: usize) {
+    for _ in 0..cycles {
+        let _ = gb.tick();
+    }
+}
+
+fn snapshot(gb: &GB) -> Vec<u8> {
+    let mut buf = vec![0; STATE_MAX_SIZE];
+    let len = gb.save_state(&mut buf).expect("save failed");
+    buf.truncate(len);
+    buf
+}
+
+#[test]
+fn round_trip_replays_exactly() {
+    let mut gb = GB::new();
+    gb.load_rom(&busy_rom(b"STATE")).unwrap();
+    // odd cycle counts land in the middle of instructions
+    run(&mut gb, 12_345);
+
+    let state = snapshot(&gb);
+    assert_eq!(state.len(), gb.state_len());
+
+    run(&mut gb, 54_321);
+    let expected = (gb.registers(), *gb.frame_buffer(), snapshot(&gb));
+
+    gb.load_state(&state).expect("load failed");
+    run(&mut gb, 54_321);
+    let actual = (gb.registers(), *gb.frame_buffer(), snapshot(&gb));
+
+    assert_eq!(expected.0, actual.0);
+    assert!(expected.1 == actual.1, "frame buffers differ");
+    assert!(expected.2 == actual.2, "states differ");
+}
+
+#[test]
+fn rejects_foreign_states() {
+    let mut gb = GB::new();
+    gb.load_rom(&busy_rom(b"STATE")).unwrap();
+    run(&mut gb, 1000);
+    let state = snapshot(&gb);
+
+    assert_eq!(gb.load_state(&state[..100]), Err(StateError::Length { expected: state.len(), actual: 100 }));
+    assert_eq!(gb.load_state(b"not a state"), Err(StateError::BadMagic));
+
+    let mut small = [0u8; 16];
+    assert_eq!(gb.save_state(&mut small), Err(StateError::BufferTooSmall(state.len())));
+
+    let mut other = GB::new();
+    other.load_rom(&busy_rom(b"OTHER")).unwrap();
+    assert_eq!(other.load_state(&state), Err(StateError::WrongRom));
+}
+
+#[test]
+fn corrupt_states_leave_the_machine_untouched() {
+    let mut gb = GB::new();
+    gb.load_rom(&busy_rom(b"STATE")).unwrap();
+    run(&mut gb, 1000);
+    let state = snapshot(&gb);
+    run(&mut gb, 54_321);
+    let before = (gb.registers(), snapshot(&gb));
+
+    // the last byte that can hold an invalid value, so that a failed load
+    // has already gone through everything before it
+    let mut scratch = GB::new();
+    scratch.load_rom(&busy_rom(b"STATE")).unwrap();
+    let mut corrupt = state.clone();
+    let pos = (0..state.len())
+        .rev()
+        .find(|&pos| {
+            corrupt[pos] = 0xFF;
+            let invalid = scratch.load_state(&corrupt) == Err(StateError::Invalid);
+            corrupt[pos] = state[pos];
+            invalid
+        })
+        .expect("no byte is checked");
+    corrupt[pos] = 0xFF;
+
+    assert_eq!(gb.load_state(&corrupt), Err(StateError::Invalid));
+    assert_eq!(gb.registers(), before.0);
+    assert!(snapshot(&gb) == before.1, "state changed");
+}
+
+#[test]
+fn rejects_running_transfers_without_bits_left() {
+    #[rustfmt::skip]
+    let prog = [
+        0x3E, 0xA5, // ld a, $A5
+        0xE0, 0x01, // ldh [SB], a
+        0x3E, 0x81, // ld a, $81
+        0xE0, 0x02, // ldh [SC], a
+        0x18, 0xFE, // jr @
+    ];
+    let mut gb = GB::new();
+    gb.load_rom(&common::program_rom(&prog, &[])).unwrap();
+    // a few bits into the transfer
+    run(&mut gb, 500);
+    let state = snapshot(&gb);
+
+    // SB, SC, then the clock, the bits left and the byte sent
+    let serial = state
+        .windows(5)
+        .position(|s| {
+            let sent = 8u32.wrapping_sub(s[3].into());
+            s[1] == 0xFF && s[4] == 0xA5 && (1..8).contains(&sent) && s[0] == !(!0xA5u8 << sent)
+        })
+        .expect("no transfer in the state");
+    let mut corrupt = state.clone();
+    corrupt[serial + 3] = 0;
+    assert_eq!(gb.load_state(&corrupt), Err(StateError::Invalid));
+}
+
+fn bess(gb: &GB) -> Vec<u8> {
+    let mut buf = vec![0; STATE_MAX_SIZE];
+    let len = gb.save_bess(&mut buf, 0).expect("BESS save failed");
+    buf.truncate(len);
+    buf
+}
+
+#[test]
+fn bess_round_trip() {
+    let mut gb = GB::new();
+    gb.load_rom(&busy_rom(b"STATE")).unwrap();
+    run(&mut gb, 12_345);
+    let state = bess(&gb);
+    assert_eq!(&state[state.len() - 4..], b"BESS");
+
+    let mut other = GB::new();
+    other.load_rom(&busy_rom(b"STATE")).unwrap();
+    other.load_bess(&state, 0).expect("BESS load failed");
+    assert_eq!(gb.registers(), other.registers());
+    assert!(gb.vram() == other.vram(), "VRAM differs");
+    assert!(gb.save_ram() == other.save_ram(), "cart RAM differs");
+    // the PPU starts the line over, so only a second trip is exact
+    let state = bess(&other);
+    gb.load_bess(&state, 0).expect("BESS load failed");
+    assert!(state == bess(&gb), "states differ");
+}
+
+#[test]
+fn bess_rejects_foreign_states() {
+    let mut gb = GB::new();
+    gb.load_rom(&busy_rom(b"STATE")).unwrap();
+    run(&mut gb, 1000);
+    let state = bess(&gb);
+
+    let mut other = GB::new();
+    other.load_rom(&busy_rom(b"OTHER")).unwrap();
+    let regs = other.registers();
+    assert_eq!(other.load_bess(&state, 0), Err(StateError::WrongRom));
+    assert_eq!(other.load_bess(&state[..state.len() - 1], 0), Err(StateError::BadMagic));
+
+    // point the footer past the blocks
+    let mut broken = state.clone();
+    let end = broken.len() - 8;
+    broken[end..end + 4].copy_from_slice(&(end as u32 - 4).to_le_bytes());
+    assert_eq!(gb.load_bess(&broken, 0), Err(StateError::Invalid));
+
+    // a CORE block from a future major version
+    let core = state.windows(4).position(|id| id == b"CORE").unwrap() + 8;
+    let mut future = state.clone();
+    future[core..core + 2].copy_from_slice(&2u16.to_le_bytes());
+    assert_eq!(gb.load_bess(&future, 0), Err(StateError::Version(2)));
+
+    assert_eq!(other.registers(), regs);
+}
+
+// records every frame, returning the snapshots pushed
+fn record(gb: &mut GB, rewind: &mut Rewind, frames: usize) -> Vec<Vec<u8>> {
+    (0..frames)
+        .map(|_| {
+            run(gb, CYCLES_PER_FRAME);
+            rewind.push(gb);
+            snapshot(gb)
+        })
+        .collect()
+}
+
+#[test]
+fn rewind_steps_back_frame_by_frame() {
+    let mut gb = GB::new();
+    gb.load_rom(&busy_rom(b"STATE")).unwrap();
+    let mut rewind = Rewind::new(0x100000, 1);
+    let mut history = record(&mut gb, &mut rewind, 20);
+    assert_eq!(rewind.len(), 20);
+
+    history.pop();
+    while let Some(expected) = history.pop() {
+        assert!(rewind.step_back(&mut gb));
+        assert!(snapshot(&gb) == expected, "{} frames in", history.len() + 1);
+    }
+    assert!(!rewind.step_back(&mut gb));
+}
+
+#[test]
+fn rewind_drops_the_oldest_frames() {
+    let mut gb = GB::new();
+    gb.load_rom(&busy_rom(b"STATE")).unwrap();
+    // room for a few deltas, snapshots every other frame
+    let mut rewind = Rewind::new(0x800, 2);
+    let history = record(&mut gb, &mut rewind, 200);
+    let kept = rewind.len();
+    assert!(kept > 1 && kept < 100, "{kept} snapshots kept");
+
+    // the first frame and every other one after it were kept, so the
+    // newest snapshot is already a step back
+    assert!(rewind.step_back(&mut gb));
+    assert!(snapshot(&gb) == history[198]);
+    assert!(rewind.step_back(&mut gb));
+    assert!(snapshot(&gb) == history[196]);
+
+    // and recording resumes from there
+    record(&mut gb, &mut rewind, 10);
+    let mut steps = 0;
+    while rewind.step_back(&mut gb) {
+        steps += 1;
+    }
+    assert!(steps >= 5 && steps < kept + 5, "{steps} steps back");
+}
@@ -0,0 +1,80 @@
+// the timer checked with hand assembled ROMs that stop at LD B, B
+// see https://gbdev.io/pandocs/Timer_Obscure_Behaviour.html
+
+mod common;
+
+const DIV: u8 = 0x04;
+const TIMA: u8 = 0x05;
+const TMA: u8 = 0x06;
+const TAC: u8 = 0x07;
+
+// TIMA in A after resetting the counter with TAC at 4096 Hz, waiting
+// about 4 * delay M-cycles, then running glitch with A = 0
+fn tima_after(delay: u8, glitch: &[u8]) -> u8 {
+    #[rustfmt::skip]
+    let prog = [
+        &[
+            0x3E, 0x04,   // ld a, $04
+            0xE0, TAC,    // ldh [TAC], a
+            0xE0, DIV,    // ldh [DIV], a
+            0xAF,         // xor a
+            0xE0, TIMA,   // ldh [TIMA], a
+            0x0E, delay,  // ld c, delay
+            0x0D,         // wait: dec c
+            0x20, 0xFD,   // jr nz, wait
+        ][..],
+        glitch,
+        &[
+            0xF0, TIMA,   // ldh a, [TIMA]
+            0x40,         // ld b, b
+        ],
+    ]
+    .concat();
+    (common::run_to_break(&common::program_rom(&prog, &[]), 1).af >> 8) as u8
+}
+
+#[test]
+fn div_write_ticks_tima() {
+    // TIMA counts falling edges of bit 9 of the counter, which resetting
+    // it makes as well once 128 M-cycles have passed
+    assert_eq!(tima_after(10, &[0xE0, DIV]), 0);
+    assert_eq!(tima_after(40, &[0xE0, DIV]), 1);
+}
+
+#[test]
+fn tac_write_ticks_tima() {
+    // so does turning the timer off
+    assert_eq!(tima_after(10, &[0xE0, TAC]), 0);
+    assert_eq!(tima_after(40, &[0xE0, TAC]), 1);
+}
+
+#[test]
+fn tima_reload_delay() {
+    // with TMA at $FF every increment overflows, and TIMA reads 0 for the
+    // one M-cycle before the reload; sampling every 11 M-cycles against
+    // increments every 4 catches that 1 time in 4
+    #[rustfmt::skip]
+    let prog = [
+        0x3E, 0xFF,   // ld a, $FF
+        0xE0, TMA,    // ldh [TMA], a
+        0xE0, TIMA,   // ldh [TIMA], a
+        0x3E, 0x05,   // ld a, $05
+        0xE0, TAC,    // ldh [TAC], a
+        0x0E, TIMA,   // ld c, TIMA
+        0x16, 0x00,   // ld d, 0
+        0x1E, 0x10,   // ld e, 16
+        0xF2,         // loop: ldh a, [c]
+        0xA7,         // and a
+        0x20, 0x01,   // jr nz, .skip
+        0x14,         // inc d
+        0x1D,         // .skip: dec e
+        0x00,         // nop
+        0x20, 0xF7,   // jr nz, loop
+        0xF0, TAC,    // ldh a, [TAC]
+        0x40,         // ld b, b
+    ];
+    let regs = common::run_to_break(&common::program_rom(&prog, &[]), 1);
+    assert_eq!(regs.de >> 8, 4, "{regs:04X?}");
+    // the unused TAC bits read 1
+    assert_eq!(regs.af >> 8, 0xFD);
+}